@@ -0,0 +1,122 @@
+// A small benchmark, run with `cargo bench`. It doesn't need any extra
+// dependencies, it just times a few loops with `Instant` and counts the
+// allocations made along the way with a wrapper around the system allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use linked_hashmap::HashMap;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    // Passed through rather than left to the default, which zeroes the
+    // memory by hand, and would make every new table look like a pause.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const N: u64 = 100_000;
+const ROUNDS: u32 = 10;
+
+// measure runs `f` a few times and reports the fastest run, along with how
+// many allocations a single run made.
+fn measure<T>(name: &str, mut f: impl FnMut() -> T) {
+    let mut best = Duration::MAX;
+    let mut allocations = 0;
+    for _ in 0..ROUNDS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        black_box(f());
+        best = best.min(start.elapsed());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    println!(
+        "{:<24} {:>10.2} ms {:>10} allocations",
+        name,
+        best.as_secs_f64() * 1000.0,
+        allocations
+    );
+}
+
+// worst_insert reports the slowest single insert while loading the map up,
+// which is where a resize shows up as a pause. Like `measure`, it keeps the
+// best of a few runs, so one unlucky context switch doesn't count.
+fn worst_insert(name: &str, incremental: bool) -> Duration {
+    let mut best = Duration::MAX;
+    for _ in 0..3 {
+        let mut worst = Duration::ZERO;
+        let mut map = HashMap::new();
+        map.set_incremental_resize(incremental);
+        for i in 0..10 * N {
+            let start = Instant::now();
+            map.insert(i, i);
+            worst = worst.max(start.elapsed());
+        }
+        black_box(map);
+        best = best.min(worst);
+    }
+    println!("{:<24} {:>10.2} ms", name, best.as_secs_f64() * 1000.0);
+    best
+}
+
+fn main() {
+    measure("insert", || {
+        let mut map = HashMap::new();
+        for i in 0..N {
+            map.insert(i, i);
+        }
+        map
+    });
+
+    let map: HashMap<u64, u64> = (0..N).map(|i| (i, i)).collect();
+    measure("get (hit)", || (0..N).filter_map(|i| map.get(&i)).count());
+    measure("get (miss)", || (N..2 * N).filter_map(|i| map.get(&i)).count());
+
+    measure("insert + remove", || {
+        let mut map = HashMap::new();
+        for i in 0..N {
+            map.insert(i, i);
+        }
+        for i in 0..N {
+            map.remove(&i);
+        }
+        map
+    });
+
+    measure("iter", || map.iter().map(|(_, &v)| v).sum::<u64>());
+
+    let all_at_once = worst_insert("worst insert", false);
+    let incremental = worst_insert("worst insert (incr)", true);
+
+    // In incremental mode neither the buckets nor the entries are moved all
+    // in one go, so the worst pause should be a small part of a full resize.
+    assert!(
+        incremental * 4 < all_at_once,
+        "incremental resize still pauses for {:?}",
+        incremental
+    );
+}
@@ -39,7 +39,7 @@ fn main() {
     contacts.remove(&"Ashley"); 
 
     // `HashMap::iter()` returns an iterator that yields 
-    // (&'a key, &'a value) pairs in arbitrary order.
+    // (&'a key, &'a value) pairs in the order they were inserted.
     for (contact, number) in contacts.into_iter() {
         println!("Calling {}: {}", contact, call(number)); 
     }
@@ -0,0 +1,175 @@
+// Chunks is where the map keeps its entries. It's a Vec split up into
+// chunks of a fixed size, so growing it only ever allocates a new chunk,
+// rather than copying everything across to a buffer twice the size, which
+// on a big map is a pause of several milliseconds all on one insert.
+//
+// The first chunk starts small and grows like a normal Vec, so small maps
+// don't pay for a whole chunk up front. It stops at full size, so it never
+// has more than one chunk's worth to copy.
+
+use std::collections::TryReserveError;
+use std::ops::{Index, IndexMut};
+
+const CHUNK_BITS: u32 = 10;
+const CHUNK: usize = 1 << CHUNK_BITS;
+
+#[derive(Clone)]
+pub(crate) struct Chunks<T> {
+    // Every chunk before the one `len` ends in is full, and every one after
+    // it is empty, with its memory set aside by `try_reserve`.
+    chunks: Vec<Vec<T>>,
+    len: usize,
+}
+
+impl<T> Chunks<T> {
+    pub(crate) fn new() -> Self {
+        Chunks {
+            chunks: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let mut chunks = Chunks::new();
+        chunks.try_reserve(capacity).expect("memory allocation failed");
+        chunks
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.chunks.iter().map(Vec::capacity).sum()
+    }
+
+    pub(crate) fn push(&mut self, value: T) {
+        let at = self.len >> CHUNK_BITS;
+        if at == self.chunks.len() {
+            self.chunks.push(Vec::new());
+        }
+
+        // Only a chunk which isn't full size yet can run out of room, so
+        // this is only ever copying less than one chunk's worth. The first
+        // one grows a bit at a time, and the rest go straight to full size.
+        let chunk = &mut self.chunks[at];
+        if chunk.len() == chunk.capacity() {
+            let grow_to = match at {
+                0 => (chunk.capacity() * 2).clamp(4, CHUNK),
+                _ => CHUNK,
+            };
+            chunk.reserve_exact(grow_to - chunk.len());
+        }
+        chunk.push(value);
+        self.len += 1;
+    }
+
+    // try_reserve makes sure there's room for `additional` more, by growing
+    // the chunks it will need to their full size, and adding any missing.
+    pub(crate) fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let needed = self.len + additional;
+        let nchunks = needed.div_ceil(CHUNK);
+
+        // Asking for the list of chunks first means a request for far too
+        // much fails straight away, rather than after filling up memory.
+        self.chunks.try_reserve(nchunks.saturating_sub(self.chunks.len()))?;
+        for at in 0..nchunks {
+            if at == self.chunks.len() {
+                self.chunks.push(Vec::new());
+            }
+            let chunk = &mut self.chunks[at];
+            let wanted = (needed - at * CHUNK).min(CHUNK);
+            if chunk.capacity() < wanted {
+                chunk.try_reserve_exact(wanted - chunk.len())?;
+            }
+        }
+        Ok(())
+    }
+
+    // iter goes through everything in the order it was pushed, which is
+    // quicker than indexing for each one.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flatten()
+    }
+
+    // clear drops everything, but keeps hold of the memory.
+    pub(crate) fn clear(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.clear();
+        }
+        self.len = 0;
+    }
+
+    // as_mut_ptr hands out the chunks for `slot`.
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut Vec<T> {
+        self.chunks.as_mut_ptr()
+    }
+}
+
+// slot is a pointer to the value at `index`, given the chunks from
+// `as_mut_ptr`. It's for `IterMut`, which can't borrow all the chunks
+// again for every entry while it still has others handed out.
+//
+// SAFETY: `chunks` has to come from `as_mut_ptr` on chunks which are still
+// mutably borrowed, and `index` has to be less than their length.
+pub(crate) unsafe fn slot<T>(chunks: *mut Vec<T>, index: usize) -> *mut T {
+    // `Vec::as_mut_ptr` promises not to make a reference to the chunk's
+    // contents, so this doesn't touch any values which are handed out.
+    (*chunks.add(index >> CHUNK_BITS)).as_mut_ptr().add(index & (CHUNK - 1))
+}
+
+impl<T> Index<usize> for Chunks<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.chunks[index >> CHUNK_BITS][index & (CHUNK - 1)]
+    }
+}
+
+impl<T> IndexMut<usize> for Chunks<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.chunks[index >> CHUNK_BITS][index & (CHUNK - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let mut chunks = Chunks::new();
+        for i in 0..3 * CHUNK + 5 {
+            chunks.push(i);
+        }
+        assert_eq!(chunks.len(), 3 * CHUNK + 5);
+        assert!((0..chunks.len()).all(|i| chunks[i] == i));
+        chunks[CHUNK] = 0;
+        assert_eq!(chunks.clone()[CHUNK], 0);
+
+        // Growing never moves a full chunk
+        let first = &chunks[0] as *const usize;
+        chunks.try_reserve(10 * CHUNK).unwrap();
+        assert!(chunks.capacity() >= 13 * CHUNK + 5);
+        for i in 0..10 * CHUNK {
+            chunks.push(i);
+        }
+        assert_eq!(&chunks[0] as *const usize, first);
+
+        let capacity = chunks.capacity();
+        chunks.clear();
+        assert!(chunks.is_empty());
+        assert_eq!(chunks.capacity(), capacity);
+        chunks.push(7);
+        assert_eq!(chunks[0], 7);
+
+        // Small ones stay small
+        let mut chunks = Chunks::with_capacity(3);
+        chunks.push(1);
+        assert!(chunks.capacity() < 8);
+    }
+}
@@ -0,0 +1,157 @@
+// A group is a run of control bytes from the table which we can check all
+// at once, to find the buckets whose byte matches a hash, or which are free.
+// On x86_64 we use SSE2 to compare 16 bytes in a couple of instructions,
+// anywhere else we fall back to doing 8 at a time with bit tricks on a u64.
+
+// Control byte for a bucket which has never held anything.
+pub(crate) const EMPTY: u8 = 0b1111_1111;
+
+// Control byte for a bucket whose entry was removed. Lookups have to keep
+// probing past these, but inserts can re-use them.
+pub(crate) const DELETED: u8 = 0b1000_0000;
+
+// BitMask has a bit set for each byte in a group which matched. Iterating
+// over it gives the position of each matching byte within the group.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BitMask(u64);
+
+impl BitMask {
+    pub(crate) fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    // lowest is the position of the first matching byte.
+    pub(crate) fn lowest(self) -> Option<usize> {
+        match self.0 {
+            0 => None,
+            bits => Some(bits.trailing_zeros() as usize / imp::BITMASK_STRIDE),
+        }
+    }
+}
+
+impl Iterator for BitMask {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let bit = self.lowest()?;
+        self.0 &= self.0 - 1;
+        Some(bit)
+    }
+}
+
+pub(crate) use imp::{Group, WIDTH};
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+mod imp {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
+
+    use super::{BitMask, EMPTY};
+
+    pub(crate) const WIDTH: usize = 16;
+
+    // movemask gives one bit per byte.
+    pub(super) const BITMASK_STRIDE: usize = 1;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct Group(__m128i);
+
+    impl Group {
+        // load reads the first WIDTH bytes of `ctrl`.
+        pub(crate) fn load(ctrl: &[u8]) -> Group {
+            let bytes = &ctrl[..WIDTH];
+            // SAFETY: `bytes` is exactly 16 bytes long, and an unaligned
+            // load doesn't care where it starts.
+            unsafe { Group(_mm_loadu_si128(bytes.as_ptr() as *const __m128i)) }
+        }
+
+        pub(crate) fn match_byte(self, byte: u8) -> BitMask {
+            // SAFETY: SSE2 is always available when this module is compiled.
+            unsafe {
+                let cmp = _mm_cmpeq_epi8(self.0, _mm_set1_epi8(byte as i8));
+                BitMask(_mm_movemask_epi8(cmp) as u16 as u64)
+            }
+        }
+
+        pub(crate) fn match_empty(self) -> BitMask {
+            self.match_byte(EMPTY)
+        }
+
+        // EMPTY and DELETED are the only control bytes with the top bit
+        // set, which is exactly what movemask picks out.
+        pub(crate) fn match_empty_or_deleted(self) -> BitMask {
+            // SAFETY: SSE2 is always available when this module is compiled.
+            unsafe { BitMask(_mm_movemask_epi8(self.0) as u16 as u64) }
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+mod imp {
+    use std::convert::TryInto;
+
+    use super::BitMask;
+
+    pub(crate) const WIDTH: usize = 8;
+
+    // Matches are flagged in the top bit of each byte.
+    pub(super) const BITMASK_STRIDE: usize = 8;
+
+    const LO: u64 = 0x0101_0101_0101_0101;
+    const HI: u64 = 0x8080_8080_8080_8080;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct Group(u64);
+
+    impl Group {
+        // load reads the first WIDTH bytes of `ctrl`, always as little
+        // endian so byte `i` of the group ends up in bits `8i..8i + 8`.
+        pub(crate) fn load(ctrl: &[u8]) -> Group {
+            Group(u64::from_le_bytes(ctrl[..WIDTH].try_into().unwrap()))
+        }
+
+        // This is the "has a zero byte" trick, run on the group xor'd with
+        // the byte we want, so matching bytes become zero. It can give a
+        // false positive for a byte straight after a real match, which is
+        // fine, as every match gets its key compared anyway.
+        pub(crate) fn match_byte(self, byte: u8) -> BitMask {
+            let cmp = self.0 ^ (LO * u64::from(byte));
+            BitMask(cmp.wrapping_sub(LO) & !cmp & HI)
+        }
+
+        // EMPTY is the only control byte with both of its top two bits set.
+        pub(crate) fn match_empty(self) -> BitMask {
+            BitMask(self.0 & (self.0 << 1) & HI)
+        }
+
+        pub(crate) fn match_empty_or_deleted(self) -> BitMask {
+            BitMask(self.0 & HI)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let mut ctrl = vec![EMPTY; WIDTH];
+        ctrl[0] = 0x12;
+        ctrl[2] = DELETED;
+        ctrl[3] = 0x12;
+        ctrl[WIDTH - 1] = 0x34;
+
+        let group = Group::load(&ctrl);
+        assert_eq!(group.match_byte(0x12).collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(group.match_byte(0x34).collect::<Vec<_>>(), vec![WIDTH - 1]);
+        assert!(!group.match_byte(0x56).any());
+
+        let empty: Vec<_> = group.match_empty().collect();
+        assert_eq!(empty[0], 1);
+        assert_eq!(empty.len(), WIDTH - 4);
+        assert_eq!(group.match_empty_or_deleted().lowest(), Some(1));
+        assert_eq!(group.match_empty_or_deleted().count(), WIDTH - 3);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::hash_map;
+use std::hash::{BuildHasher, Hasher};
+
+// RandomState is the default hash builder for the map. Every one gets its
+// own pair of random SipHash keys when it's created, so someone who controls
+// the keys going into one map (say HTTP header names) can't work out ahead
+// of time which of them will collide, and pile them all into one bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RandomState {
+    k0: u64,
+    k1: u64,
+}
+
+impl RandomState {
+    pub fn new() -> RandomState {
+        // The standard library already seeds its own RandomState from the
+        // operating system, and gives each new one different keys, so we
+        // use one of those to hash a couple of constants into fresh keys,
+        // rather than pulling in a dependency to get at the system RNG.
+        let seed = hash_map::RandomState::new();
+        RandomState {
+            k0: seed.hash_one(0u8),
+            k1: seed.hash_one(1u8),
+        }
+    }
+
+    // with_seeds uses the given keys instead of random ones, which is handy
+    // for tests which need the same bucket layout every time they run.
+    pub fn with_seeds(k0: u64, k1: u64) -> RandomState {
+        RandomState { k0, k1 }
+    }
+}
+
+impl Default for RandomState {
+    fn default() -> RandomState {
+        RandomState::new()
+    }
+}
+
+impl BuildHasher for RandomState {
+    type Hasher = SipHasher13;
+
+    fn build_hasher(&self) -> SipHasher13 {
+        SipHasher13::new_with_keys(self.k0, self.k1)
+    }
+}
+
+// SipHasher13 is SipHash with 1 compression round and 3 finalization
+// rounds, which is what the standard library uses under the hood, but
+// doesn't let you construct with your own keys.
+#[derive(Clone, Debug)]
+pub struct SipHasher13 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+
+    // Bytes which didn't fill a whole word yet, and how many of them there are.
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+impl SipHasher13 {
+    pub fn new_with_keys(k0: u64, k1: u64) -> SipHasher13 {
+        SipHasher13 {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13);
+        self.v1 ^= self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16);
+        self.v3 ^= self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21);
+        self.v3 ^= self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17);
+        self.v1 ^= self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length += bytes.len();
+
+        // Top up any word left over from the last write first.
+        let mut bytes = bytes;
+        while self.ntail > 0 && !bytes.is_empty() {
+            self.tail |= u64::from(bytes[0]) << (8 * self.ntail);
+            self.ntail = (self.ntail + 1) % 8;
+            bytes = &bytes[1..];
+            if self.ntail == 0 {
+                let word = self.tail;
+                self.compress(word);
+                self.tail = 0;
+            }
+        }
+
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(word);
+            self.compress(u64::from_le_bytes(buf));
+        }
+
+        for &byte in words.remainder() {
+            self.tail |= u64::from(byte) << (8 * self.ntail);
+            self.ntail += 1;
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let word = ((self.length as u64 & 0xff) << 56) | self.tail;
+
+        state.compress(word);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hash;
+
+    #[test]
+    fn matches_std() {
+        // DefaultHasher is SipHash 1-3 with both keys set to zero.
+        fn check<T: Hash + ?Sized>(value: &T) {
+            let ours = RandomState::with_seeds(0, 0).hash_one(value);
+            let mut std = DefaultHasher::new();
+            value.hash(&mut std);
+            assert_eq!(ours, std.finish());
+        }
+
+        check("");
+        check("a");
+        check("the quick brown fox jumps over the lazy dog");
+        check(&(1u8, 2u16, 3u32, 4u64, "five"));
+        check(&[0u8; 23][..]);
+        check(&u128::MAX);
+    }
+
+    #[test]
+    fn random_keys() {
+        assert_ne!(RandomState::new(), RandomState::new());
+        assert_ne!(RandomState::new().hash_one("key"), RandomState::new().hash_one("key"));
+        assert_eq!(
+            RandomState::with_seeds(1, 2).hash_one("key"),
+            RandomState::with_seeds(1, 2).hash_one("key")
+        );
+    }
+}
@@ -0,0 +1,780 @@
+// The iterators all follow the links between the entries, starting at the
+// oldest one, so entries come out in the order they were inserted (or least
+// to most recently used, in access order). They can be run backwards from
+// the newest one too, and from both ends at once. They keep count of how
+// many are left, so they know their exact length up front, and so the two
+// ends know when they've met without having to compare where they are.
+
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::chunks::{self, Chunks};
+use crate::{HashMap, Node, NIL};
+
+impl<K, V, S> HashMap<K, V, S> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            entries: &self.entries,
+            next: self.head.get(),
+            back: self.tail.get(),
+            remaining: self.items,
+        }
+    }
+
+    // iter_mut is the same as `iter`, but hands out the values mutably.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            entries: self.entries.as_mut_ptr(),
+            next: self.head.get(),
+            back: self.tail.get(),
+            remaining: self.items,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys {
+            inner: self.into_iter(),
+        }
+    }
+
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues {
+            inner: self.into_iter(),
+        }
+    }
+
+    // drain takes every entry out of the map, in order, but leaves it with
+    // all the memory it had, ready to be filled up again. The map is empty
+    // as soon as this is called, whether or not the entries are used up.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        let next = self.head.replace(NIL);
+        let back = self.tail.replace(NIL);
+        let remaining = mem::replace(&mut self.items, 0);
+        self.free.clear();
+        self.positions.take();
+        self.buckets.clear();
+        self.old_buckets = None;
+        Drain {
+            entries: &mut self.entries,
+            next,
+            back,
+            remaining,
+        }
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    // extract_if removes and hands back the entries `pred` returns true
+    // for, one at a time, as the iterator is used. Any entries it doesn't
+    // get to, because it's dropped early, stay in the map.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, S, F>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        ExtractIf {
+            next: self.head.get(),
+            map: self,
+            pred,
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    entries: &'a Chunks<Option<Node<K, V>>>,
+    next: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A `get` in access order can move entries about under us, which
+        // might run us off the end before `remaining` gets to zero.
+        if self.remaining == 0 || self.next == NIL {
+            return None;
+        }
+
+        let node = self.entries[self.next].as_ref().expect("linked entry is vacant");
+        self.next = node.next.get();
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.back == NIL {
+            return None;
+        }
+
+        let node = self.entries[self.back].as_ref().expect("linked entry is vacant");
+        self.back = node.prev.get();
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+impl<K, V> FusedIterator for Iter<'_, K, V> {}
+
+// Clone and Debug are written out by hand, as deriving them would need K and
+// V to be Clone or Debug, even though we only hold references to them.
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Iter {
+            entries: self.entries,
+            next: self.next,
+            back: self.back,
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Iter<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a HashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+// IterMut can't hold on to a `&mut` to the entries, and index into them
+// for each one, as that would need to borrow them all again while the
+// entries it's already handed out are still borrowed. So it works from a
+// raw pointer instead, the same as `slice::IterMut` does, see `chunks::slot`.
+pub struct IterMut<'a, K, V> {
+    entries: *mut Vec<Option<Node<K, V>>>,
+    next: usize,
+    back: usize,
+    remaining: usize,
+    marker: PhantomData<&'a mut Node<K, V>>,
+}
+
+// SAFETY: IterMut is just a `&mut` to the entries, so it can go wherever
+// one of those can.
+unsafe impl<K: Send, V: Send> Send for IterMut<'_, K, V> {}
+unsafe impl<K: Sync, V: Sync> Sync for IterMut<'_, K, V> {}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        // SAFETY: `entries` came from a `&'a mut` to the map's entries, and
+        // `next` is a linked entry, so it's in bounds. Following the links
+        // visits each entry only once, and `remaining` stops us before we
+        // get to any the other end has handed out, so nothing we've handed
+        // out already points at this one.
+        let slot = unsafe { &mut *chunks::slot(self.entries, self.next) };
+        let node = slot.as_mut().expect("linked entry is vacant");
+        self.next = node.next.get();
+        self.remaining -= 1;
+        Some((&node.key, &mut node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IterMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        // SAFETY: the same as for `next`, going the other way.
+        let slot = unsafe { &mut *chunks::slot(self.entries, self.back) };
+        let node = slot.as_mut().expect("linked entry is vacant");
+        self.back = node.prev.get();
+        self.remaining -= 1;
+        Some((&node.key, &mut node.value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {}
+impl<K, V> FusedIterator for IterMut<'_, K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IterMut<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        let mut index = self.next;
+        for _ in 0..self.remaining {
+            // SAFETY: none of the `remaining` entries from `next` on have
+            // been handed out yet, so nothing else can be changing them.
+            let slot = unsafe { &*chunks::slot(self.entries, index) };
+            let node = slot.as_ref().expect("linked entry is vacant");
+            list.entry(&(&node.key, &node.value));
+            index = node.next.get();
+        }
+        list.finish()
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a mut HashMap<K, V, S> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+pub struct IntoIter<K, V> {
+    entries: Chunks<Option<Node<K, V>>>,
+    next: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        // We own the map, so we can just take each entry out of its slot,
+        // without bothering to tidy up the buckets or links behind us.
+        let node = self.entries[self.next].take().expect("linked entry is vacant");
+        self.next = node.next.get();
+        self.remaining -= 1;
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let node = self.entries[self.back].take().expect("linked entry is vacant");
+        self.back = node.prev.get();
+        self.remaining -= 1;
+        Some((node.key, node.value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IntoIter<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            entries: &self.entries,
+            next: self.next,
+            back: self.back,
+            remaining: self.remaining,
+        };
+        f.debug_list().entries(iter).finish()
+    }
+}
+
+impl<K, V, S> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            next: self.head.get(),
+            back: self.tail.get(),
+            remaining: self.items,
+            entries: self.entries,
+        }
+    }
+}
+
+// The rest just pick the key or the value out of one of the iterators above.
+
+pub struct Keys<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Keys<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<K, V> ExactSizeIterator for Keys<'_, K, V> {}
+impl<K, V> FusedIterator for Keys<'_, K, V> {}
+
+impl<K, V> Clone for Keys<'_, K, V> {
+    fn clone(&self) -> Self {
+        Keys {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: fmt::Debug, V> fmt::Debug for Keys<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub struct Values<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Values<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> ExactSizeIterator for Values<'_, K, V> {}
+impl<K, V> FusedIterator for Values<'_, K, V> {}
+
+impl<K, V> Clone for Values<'_, K, V> {
+    fn clone(&self) -> Self {
+        Values {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, V: fmt::Debug> fmt::Debug for Values<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+pub struct ValuesMut<'a, K, V> {
+    inner: IterMut<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<&'a mut V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for ValuesMut<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> ExactSizeIterator for ValuesMut<'_, K, V> {}
+impl<K, V> FusedIterator for ValuesMut<'_, K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for ValuesMut<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+pub struct IntoKeys<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+impl<K, V> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoKeys<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(k, _)| k)
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoKeys<K, V> {}
+impl<K, V> FusedIterator for IntoKeys<K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IntoKeys<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+pub struct IntoValues<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+impl<K, V> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoValues<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, v)| v)
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoValues<K, V> {}
+impl<K, V> FusedIterator for IntoValues<K, V> {}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IntoValues<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+pub struct Drain<'a, K, V> {
+    // `drain` has already reset everything else in the map, so the entries
+    // are all we need.
+    entries: &'a mut Chunks<Option<Node<K, V>>>,
+    next: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<K, V> Iterator for Drain<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let node = self.entries[self.next].take().expect("linked entry is vacant");
+        self.next = node.next.get();
+        self.remaining -= 1;
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Drain<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let node = self.entries[self.back].take().expect("linked entry is vacant");
+        self.back = node.prev.get();
+        self.remaining -= 1;
+        Some((node.key, node.value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Drain<'_, K, V> {}
+impl<K, V> FusedIterator for Drain<'_, K, V> {}
+
+// Dropping the entries which weren't used up empties `entries`, but keeps
+// its memory, the same as `Vec::clear`. If a Drain is leaked instead, they
+// just sit in slots the map has forgotten about until it's dropped.
+impl<K, V> Drop for Drain<'_, K, V> {
+    fn drop(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Drain<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            entries: self.entries,
+            next: self.next,
+            back: self.back,
+            remaining: self.remaining,
+        };
+        f.debug_list().entries(iter).finish()
+    }
+}
+
+pub struct ExtractIf<'a, K, V, S, F> {
+    map: &'a mut HashMap<K, V, S>,
+    next: usize,
+    pred: F,
+}
+
+impl<K, V, S, F> Iterator for ExtractIf<'_, K, V, S, F>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next != NIL {
+            // Move on before calling `pred`, so a panic skips this entry
+            // rather than trying it again.
+            let index = self.next;
+            let node = self.map.node_mut(index);
+            self.next = node.next.get();
+            if (self.pred)(&node.key, &mut node.value) {
+                let node = self.map.remove_index(index);
+                return Some((node.key, node.value));
+            }
+        }
+
+        self.map.shrink_if_sparse();
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.map.items))
+    }
+}
+
+impl<K, V, S, F> FusedIterator for ExtractIf<'_, K, V, S, F>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    F: FnMut(&K, &mut V) -> bool,
+{
+}
+
+impl<K, V, S, F> fmt::Debug for ExtractIf<'_, K, V, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtractIf").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HashMap;
+
+    #[test]
+    fn iterators() {
+        let mut map = HashMap::new();
+        for i in 0..10 {
+            map.insert(i, i * 10);
+        }
+        map.remove(&3);
+
+        let mut iter = map.iter();
+        assert_eq!(iter.len(), 9);
+        iter.next();
+        assert_eq!(iter.size_hint(), (8, Some(8)));
+        let rest = iter.clone();
+        assert_eq!(iter.count(), 8);
+        assert_eq!(rest.len(), 8);
+        assert_eq!(format!("{:?}", map.keys().take(3).collect::<Vec<_>>()), "[0, 1, 2]");
+
+        for (&k, v) in map.iter_mut() {
+            *v += k;
+        }
+        for v in &mut map.values_mut() {
+            *v += 1;
+        }
+        for (_, v) in &mut map {
+            *v -= 1;
+        }
+        let keys: Vec<_> = map.keys().copied().collect();
+        assert_eq!(keys, vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        let values: Vec<_> = map.values().copied().collect();
+        assert_eq!(values, keys.iter().map(|k| k * 11).collect::<Vec<_>>());
+
+        // Running out keeps them out
+        let mut values = map.values_mut();
+        assert_eq!(values.len(), 9);
+        assert_eq!(values.by_ref().count(), 9);
+        assert_eq!(values.next(), None);
+
+        let mut small = HashMap::new();
+        small.insert("a", 1);
+        small.insert("b", 2);
+        assert_eq!(format!("{:?}", small.iter()), r#"[("a", 1), ("b", 2)]"#);
+        assert_eq!(format!("{:?}", small.iter_mut()), r#"[("a", 1), ("b", 2)]"#);
+        assert_eq!(format!("{:?}", small.keys()), r#"["a", "b"]"#);
+        assert_eq!(format!("{:?}", small.values()), "[1, 2]");
+
+        let mut into_iter = small.into_iter();
+        assert_eq!(into_iter.next(), Some(("a", 1)));
+        assert_eq!(format!("{:?}", into_iter), r#"[("b", 2)]"#);
+        assert_eq!(into_iter.len(), 1);
+        let copy: HashMap<_, _> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(copy.into_keys().collect::<Vec<_>>(), keys);
+        assert_eq!(map.into_values().len(), 9);
+    }
+
+    #[test]
+    fn double_ended() {
+        let mut map: HashMap<_, _> = (0..10).map(|i| (i, i)).collect();
+        map.remove(&3);
+        map.insert(3, 3);
+        assert_eq!(map.keys().rev().copied().collect::<Vec<_>>(), [3, 9, 8, 7, 6, 5, 4, 2, 1, 0]);
+        assert_eq!(map.values().next_back(), Some(&3));
+
+        // The ends meet in the middle, and don't hand anything out twice
+        let mut iter = map.iter();
+        assert_eq!(iter.next(), Some((&0, &0)));
+        assert_eq!(iter.next_back(), Some((&3, &3)));
+        assert_eq!(iter.len(), 8);
+        let rest: Vec<_> = iter.by_ref().rev().take(7).map(|(&k, _)| k).collect();
+        assert_eq!(rest, [9, 8, 7, 6, 5, 4, 2]);
+        assert_eq!(iter.next(), Some((&1, &1)));
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.next(), None);
+
+        let mut iter = map.iter_mut();
+        while let (Some((_, a)), Some((_, b))) = (iter.next(), iter.next_back()) {
+            std::mem::swap(a, b);
+        }
+        for v in map.values_mut().rev().take(2) {
+            *v += 100;
+        }
+        let values: Vec<_> = map.values().copied().collect();
+        assert_eq!(values, [3, 9, 8, 7, 6, 5, 4, 2, 101, 100]);
+
+        // Popping the newest entries off the end
+        let mut into_iter = map.clone().into_iter();
+        assert_eq!(into_iter.next_back(), Some((3, 100)));
+        assert_eq!(into_iter.next_back(), Some((9, 101)));
+        assert_eq!(into_iter.next(), Some((0, 3)));
+        assert_eq!(format!("{:?}", into_iter.by_ref().rev().take(2).collect::<Vec<_>>()), "[(8, 2), (7, 4)]");
+        assert_eq!(into_iter.len(), 5);
+        assert_eq!(map.clone().into_keys().next_back(), Some(3));
+        assert_eq!(map.clone().into_values().rev().nth(1), Some(101));
+        assert_eq!(map.drain().rev().map(|(k, _)| k).collect::<Vec<_>>(), [3, 9, 8, 7, 6, 5, 4, 2, 1, 0]);
+    }
+
+    #[test]
+    fn drain() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        map.remove(&50);
+        let nbuckets = map.buckets.nbuckets();
+        let drained: Vec<_> = map.drain().map(|(k, _)| k).collect();
+        assert_eq!(drained, (0..100).filter(|&i| i != 50).collect::<Vec<_>>());
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(map.buckets.nbuckets(), nbuckets);
+
+        // The rest go when it's dropped, and the map works as normal after
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        let mut drain = map.drain();
+        assert_eq!(drain.len(), 10);
+        assert_eq!(drain.next(), Some((0, 0)));
+        drop(drain);
+        assert!(map.is_empty());
+        map.insert(1, 1);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&1, &1)]);
+        assert_eq!(map.get(&2), None);
+    }
+
+    #[test]
+    fn retain() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        map.retain(|&k, v| {
+            *v *= 2;
+            k % 3 == 0
+        });
+        assert_eq!(map.len(), 34);
+        assert_eq!(map.keys().copied().collect::<Vec<_>>(), (0..100).step_by(3).collect::<Vec<_>>());
+        assert!(map.iter().all(|(k, v)| *v == k * 2));
+
+        // A panic part way through leaves what's been done so far
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            map.retain(|&k, _| if k == 51 { panic!() } else { k > 30 })
+        }));
+        assert!(result.is_err());
+        assert_eq!(map.len(), 34 - 11);
+        assert_eq!(map.iter().count(), map.len());
+        assert!(map.keys().all(|&k| k > 30 && map.get(&k).is_some()));
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut map: HashMap<_, _> = (0..20).map(|i| (i, i)).collect();
+        let odd: Vec<_> = map.extract_if(|k, _| k % 2 == 1).take(2).collect();
+        assert_eq!(odd, vec![(1, 1), (3, 3)]);
+        assert_eq!(map.len(), 18);
+        assert!(map.contains_key(&5));
+
+        let big: Vec<_> = map.extract_if(|&k, v| {
+            *v += 1;
+            k >= 15
+        }).collect();
+        assert_eq!(big, vec![(15, 16), (16, 17), (17, 18), (18, 19), (19, 20)]);
+        let keys: Vec<_> = map.keys().copied().collect();
+        assert_eq!(keys, vec![0, 2, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(map.get(&14), Some(&15));
+    }
+}
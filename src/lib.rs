@@ -1,59 +1,717 @@
 use std::mem;
 use std::borrow::Borrow;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::cell::{Cell, RefCell};
 
+mod chunks;
+mod group;
+mod hash;
+mod iter;
+mod lru;
+mod order;
+mod position;
+mod raw_entry;
+mod resize;
+mod table;
+mod traits;
 
-const INITIAL_NBUCKETS: usize = 1;
+pub use hash::{RandomState, SipHasher13};
+pub use iter::{
+    Drain, ExtractIf, IntoIter, IntoKeys, IntoValues, Iter, IterMut, Keys, Values, ValuesMut,
+};
+pub use lru::LruCache;
+pub use raw_entry::{KeyHash, RawEntryBuilder, RawEntryBuilderMut, RawEntryMut, RawVacantEntryMut};
+pub use resize::{ResizePolicy, TableSize};
 
-pub struct HashMap<K, V> {
-    buckets: Vec<Vec<(K, V)>>,
+use chunks::Chunks;
+use position::Positions;
+use table::RawTable;
+
+// How many old buckets each insert, remove etc moves into the new table,
+// while an incremental resize is going on.
+const MIGRATE_STEP: usize = 32;
+
+// How many groups an insert can probe past before we treat it as a sign
+// that someone is deliberately feeding the map colliding keys.
+const COLLISION_THRESHOLD: usize = 8;
+
+// NIL marks the end of the linked list, the same way a null pointer would
+// in a pointer based linked list. We link entries by their index in the
+// `entries` vector rather than by pointer, so no unsafe is needed.
+const NIL: usize = usize::MAX;
+
+// RemoveEldest is consulted every time a new key is added to the map, the
+// same as Java's `removeEldestEntry`. It's given the length of the map and
+// its eldest entry, which is the oldest, or least recently used in access
+// order, and returns true if that entry should be removed.
+//
+// It keeps being asked until it returns false, so it can trim a map down
+// by weight or age, rather than just one entry at a time. The entry that
+// was just inserted is never offered up for removal.
+pub trait RemoveEldest<K, V> {
+    fn remove_eldest(&mut self, len: usize, key: &K, value: &V) -> bool;
+}
+
+// Any closure with the right signature can be used as a policy, e.g.
+// `map.set_remove_eldest(|len, _: &_, _: &_| len > 100)`.
+impl<K, V, F> RemoveEldest<K, V> for F
+where
+    F: FnMut(usize, &K, &V) -> bool,
+{
+    fn remove_eldest(&mut self, len: usize, key: &K, value: &V) -> bool {
+        self(len, key, value)
+    }
+}
+
+// A boxed up policy works too, for picking one at runtime.
+impl<K, V> RemoveEldest<K, V> for Box<dyn RemoveEldest<K, V> + Send> {
+    fn remove_eldest(&mut self, len: usize, key: &K, value: &V) -> bool {
+        (**self).remove_eldest(len, key, value)
+    }
+}
+
+// EldestPolicy is how the map holds on to a RemoveEldest policy. It's
+// shared, rather than boxed, so cloning the map doesn't need the policy to
+// be Clone, and the Mutex keeps the map Send when a clone is on another
+// thread.
+type EldestPolicy<K, V> = Arc<Mutex<dyn RemoveEldest<K, V> + Send>>;
+
+// Equivalent is how lookups compare the key they were given with the keys
+// in the map, the same as in hashbrown and indexmap. Anything a key can be
+// borrowed as already works, e.g. a `&str` for a `String` key, but it can
+// also be implemented for types a key can't be borrowed as, like looking
+// up `(String, u32)` keys with a `(&str, u32)` shaped struct. It has to hash
+// exactly the same as the key it's equivalent to.
+pub trait Equivalent<K: ?Sized> {
+    fn equivalent(&self, key: &K) -> bool;
+}
+
+impl<Q, K> Equivalent<K> for Q
+where
+    Q: Eq + ?Sized,
+    K: Borrow<Q> + ?Sized,
+{
+    fn equivalent(&self, key: &K) -> bool {
+        self == key.borrow()
+    }
+}
+
+// CollisionStats counts how often the map has had to defend itself against
+// an entry landing too far from its bucket, see `set_collision_threshold`.
+// Seeing these go up with the default hasher most likely means an attack.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollisionStats {
+    // Number of times an insert had to probe past more groups than the threshold.
+    pub long_chains: usize,
+    // Number of times the map picked a new salt and rehashed everything.
+    pub reseeds: usize,
+}
+
+// Where `find` found an entry, in the current buckets, or in the old ones
+// if they're still being moved across after an incremental resize.
+#[derive(Clone, Copy)]
+enum Bucket {
+    Current(usize),
+    Old(usize),
+}
+
+// TryReserveError is returned by `try_reserve` when the map can't grow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryReserveError {
+    // The number of buckets or entries needed is more than could ever be
+    // allocated.
+    CapacityOverflow,
+    // The allocator couldn't give us the memory.
+    AllocError,
+}
+
+impl fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryReserveError::CapacityOverflow => f.write_str("capacity overflow"),
+            TryReserveError::AllocError => f.write_str("memory allocation failed"),
+        }
+    }
+}
+
+impl Error for TryReserveError {}
+
+// Node is a single entry in the map. As well as the key and value, it
+// holds the index of the entry inserted before it, and the one after it,
+// which is what keeps track of the insertion order.
+//
+// It also keeps the key's hash, so resizing never has to hash the keys
+// again, and lookups can skip comparing keys whose hash doesn't match.
+//
+// The links are in `Cell`s, so `get` can move an entry to the back in
+// access order, with only `&self`. That's also why the map isn't `Sync`.
+#[derive(Clone)]
+struct Node<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+    prev: Cell<usize>,
+    next: Cell<usize>,
+}
+
+pub struct HashMap<K, V, S = RandomState> {
+    // The buckets only hold the index of an entry in `entries`, so resizing
+    // just shuffles indices around, and never moves the entries themselves,
+    // which means the links between them stay valid.
+    buckets: RawTable,
+    entries: Chunks<Option<Node<K, V>>>,
+
+    // Slots in `entries` which have been removed, and can be re-used
+    // by the next insert.
+    free: Vec<usize>,
+
+    // First and last entries in iteration order.
+    head: Cell<usize>,
+    tail: Cell<usize>,
     items: usize,
+
+    // Where each entry is in the order, for looking them up by position.
+    // It's only built once something asks for a position, see position.rs.
+    positions: RefCell<Option<Positions>>,
+
+    // When true, looking an entry up moves it to the back, so iteration goes
+    // from least to most recently used, rather than in insertion order.
+    access_order: bool,
+
+    remove_eldest: Option<EldestPolicy<K, V>>,
+    resize_policy: ResizePolicy,
+
+    // Builds the hasher used for every key, see `with_hasher`.
+    hash_builder: S,
+
+    // Mixed into every hash once it isn't zero, changing it scatters the
+    // entries into completely different buckets, see `check_chain`.
+    salt: u64,
+    collision_threshold: usize,
+    collision_stats: CollisionStats,
+
+    // Number of buckets the last time we reseeded.
+    reseeded_at: usize,
+
+    // When incremental resize is on, growing the map doesn't move every
+    // entry across in one go. Instead the old buckets hang around, and a
+    // few at a time get moved over by each insert, remove etc, with lookups
+    // checking both until it's done, the same as a Redis dict.
+    incremental_resize: bool,
+    old_buckets: Option<RawTable>,
+    // How far through the old buckets we've got.
+    migrated: usize,
 }
 
-impl<K, V> HashMap<K, V> {
+impl<K, V> HashMap<K, V, RandomState> {
     pub fn new() -> Self {
+        HashMap::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashMap::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+
+    // with_seed hashes with fixed SipHash keys, rather than random ones, so
+    // the map behaves the same way every time. Only use this for tests, as
+    // it gives up the protection random keys have against collision attacks.
+    pub fn with_seed(k0: u64, k1: u64) -> Self {
+        HashMap::with_hasher(RandomState::with_seeds(k0, k1))
+    }
+
+    // with_access_order is the same as Java's `LinkedHashMap(accessOrder)`,
+    // passing true means `get`, `entry`, and `insert` on an existing key all
+    // move the entry they touch to the back of the map. See
+    // `set_access_order` for a map with some other hasher.
+    pub fn with_access_order(access_order: bool) -> Self {
+        let mut map = HashMap::new();
+        map.set_access_order(access_order);
+        map
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    // with_hasher lets you swap out the default SipHash based hasher, for
+    // something faster like FxHash when the keys are trusted integers.
+    pub fn with_hasher(hash_builder: S) -> Self {
         HashMap {
-            buckets: Vec::new(),
+            buckets: RawTable::new(),
+            entries: Chunks::new(),
+            free: Vec::new(),
+            head: Cell::new(NIL),
+            tail: Cell::new(NIL),
             items: 0,
+            positions: RefCell::new(None),
+            access_order: false,
+            remove_eldest: None,
+            resize_policy: ResizePolicy::new(),
+            hash_builder,
+            salt: 0,
+            collision_threshold: COLLISION_THRESHOLD,
+            collision_stats: CollisionStats::default(),
+            reseeded_at: 0,
+            incremental_resize: false,
+            old_buckets: None,
+            migrated: 0,
+        }
+    }
+
+    // with_capacity_and_hasher sets aside enough room up front for
+    // `capacity` entries to be inserted without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        let mut map = HashMap::with_hasher(hash_builder);
+        let nbuckets = map.resize_policy.buckets_for(capacity).expect("capacity overflow");
+        map.buckets = RawTable::with_buckets(nbuckets, map.resize_policy.table_size);
+        map.entries = Chunks::with_capacity(capacity);
+        map
+    }
+
+    // capacity is how many entries the map can hold before it has to
+    // resize, it might be able to hold a few more.
+    pub fn capacity(&self) -> usize {
+        self.resize_policy.max_items(self.buckets.nbuckets())
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    // set_access_order switches between insertion order and access order,
+    // see `with_access_order`. The entries stay where they are, only the
+    // lookups after it behave differently.
+    pub fn set_access_order(&mut self, access_order: bool) {
+        self.access_order = access_order;
+    }
+
+    // set_collision_threshold changes how many groups of buckets an insert
+    // can probe past before the map reseeds and rehashes itself.
+    pub fn set_collision_threshold(&mut self, threshold: usize) {
+        self.collision_threshold = threshold;
+    }
+
+    pub fn collision_stats(&self) -> CollisionStats {
+        self.collision_stats
+    }
+
+    // set_remove_eldest installs a policy which decides when to throw away
+    // old entries, replacing any existing one. Cloning the map shares the
+    // policy with the clone. It has to be Send, so the map still is.
+    pub fn set_remove_eldest<P>(&mut self, policy: P)
+    where
+        P: RemoveEldest<K, V> + Send + 'static,
+    {
+        self.remove_eldest = Some(Arc::new(Mutex::new(policy)));
+    }
+
+    pub fn clear_remove_eldest(&mut self) {
+        self.remove_eldest = None;
+    }
+
+    pub fn resize_policy(&self) -> &ResizePolicy {
+        &self.resize_policy
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    // compact moves the entries down to the front of `entries`, in order,
+    // so there are no free slots left in between, and leaves room for
+    // `capacity` of them. Every index changes, so the buckets need to be
+    // rebuilt afterwards.
+    fn compact(&mut self, capacity: usize) {
+        let mut entries = Chunks::with_capacity(capacity.max(self.items));
+        let mut index = self.head.get();
+        while index != NIL {
+            let node = self.entries[index].take().expect("linked entry is vacant");
+            index = node.next.get();
+            node.prev.set(entries.len().wrapping_sub(1));
+            node.next.set(entries.len() + 1);
+            entries.push(Some(node));
         }
+
+        // The first entry's prev wrapped round to NIL, but the last one
+        // still needs its next fixing.
+        if let Some(last) = entries.len().checked_sub(1) {
+            entries[last].as_ref().expect("linked entry is vacant").next.set(NIL);
+        }
+        self.head.set(if entries.is_empty() { NIL } else { 0 });
+        self.tail.set(entries.len().wrapping_sub(1));
+        self.entries = entries;
+        self.free = Vec::new();
+        self.positions.take();
+    }
+
+    fn node(&self, index: usize) -> &Node<K, V> {
+        self.entries[index].as_ref().expect("linked entry is vacant")
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut Node<K, V> {
+        self.entries[index].as_mut().expect("linked entry is vacant")
+    }
+
+    // alloc stores a new node at the back of the list, re-using a free slot
+    // if there is one, and returns its index.
+    fn alloc(&mut self, hash: u64, key: K, value: V) -> usize {
+        let node = Node {
+            hash,
+            key,
+            value,
+            prev: Cell::new(NIL),
+            next: Cell::new(NIL),
+        };
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = Some(node);
+                index
+            }
+            None => {
+                self.entries.push(Some(node));
+                self.entries.len() - 1
+            }
+        };
+
+        self.link_back(index);
+        self.items += 1;
+        index
+    }
+
+    // release unlinks a node from the list and frees up its slot. It's up to
+    // the caller to remove the index from the buckets.
+    fn release(&mut self, index: usize) -> Node<K, V> {
+        self.unlink(index);
+        self.free.push(index);
+        self.items -= 1;
+        self.entries[index].take().expect("linked entry is vacant")
+    }
+
+    // link_back hooks an unlinked node onto the end of the list.
+    fn link_back(&self, index: usize) {
+        let tail = self.tail.get();
+        let node = self.node(index);
+        node.prev.set(tail);
+        node.next.set(NIL);
+
+        match tail {
+            NIL => self.head.set(index),
+            tail => self.node(tail).next.set(index),
+        }
+        self.tail.set(index);
+
+        if let Some(positions) = self.positions.borrow_mut().as_mut() {
+            positions.push(index);
+        }
+    }
+
+    // link_front hooks an unlinked node onto the start of the list.
+    fn link_front(&self, index: usize) {
+        let head = self.head.get();
+        let node = self.node(index);
+        node.prev.set(NIL);
+        node.next.set(head);
+
+        match head {
+            NIL => self.tail.set(index),
+            head => self.node(head).prev.set(index),
+        }
+        self.head.set(index);
+        self.positions.take();
+    }
+
+    // link_after hooks an unlinked node into the list, just after `anchor`.
+    fn link_after(&self, index: usize, anchor: usize) {
+        let next = self.node(anchor).next.get();
+        let node = self.node(index);
+        node.prev.set(anchor);
+        node.next.set(next);
+
+        self.node(anchor).next.set(index);
+        match next {
+            NIL => self.tail.set(index),
+            next => self.node(next).prev.set(index),
+        }
+        self.positions.take();
+    }
+
+    // link_before is the same as `link_after`, but just before `anchor`.
+    fn link_before(&self, index: usize, anchor: usize) {
+        match self.node(anchor).prev.get() {
+            NIL => self.link_front(index),
+            prev => self.link_after(index, prev),
+        }
+    }
+
+    // unlink takes a node out of the list, joining up its neighbours,
+    // but leaves it sat in its slot.
+    fn unlink(&self, index: usize) {
+        let (prev, next) = {
+            let node = self.node(index);
+            (node.prev.get(), node.next.get())
+        };
+
+        // Only the last one can come out without moving any others up
+        if index == self.tail.get() {
+            if let Some(positions) = self.positions.borrow_mut().as_mut() {
+                positions.pop();
+            }
+        } else {
+            self.positions.take();
+        }
+
+        match prev {
+            NIL => self.head.set(next),
+            prev => self.node(prev).next.set(next),
+        }
+        match next {
+            NIL => self.tail.set(prev),
+            next => self.node(next).prev.set(prev),
+        }
+    }
+
+    // touch is called whenever an existing entry is accessed, and moves
+    // it to the back if the map is in access order. The links are all
+    // `Cell`s, so it only needs `&self`, which lets `get` call it too.
+    fn touch(&self, index: usize) {
+        if self.access_order {
+            self.move_back(index);
+        }
+    }
+
+    fn move_back(&self, index: usize) {
+        if self.tail.get() != index {
+            self.unlink(index);
+            self.link_back(index);
+        }
+    }
+}
+
+impl<K, V, S> Default for HashMap<K, V, S>
+where
+    S: Default,
+{
+    fn default() -> Self {
+        HashMap::with_hasher(S::default())
+    }
+}
+
+// OccupiedEntry is a key which is already in the map, handed out by
+// `entry`. It holds on to the key `entry` was called with, in case you want
+// to swap it in with `replace_key`. Ones from elsewhere don't have one.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    key: Option<K>,
+    map: &'a mut HashMap<K, V, S>,
+    bucket: Bucket,
+    index: usize,
+}
+
+impl<'a, K: 'a, V: 'a, S: 'a> OccupiedEntry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.map.node(self.index).key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.map.node(self.index).value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.node_mut(self.index).value
+    }
+
+    // into_mut is the same as `get_mut`, but the reference lives as long
+    // as the map borrow, rather than the entry.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.node_mut(self.index).value
+    }
+
+    // insert replaces the value, returning the old one. The entry stays
+    // where it is in the map.
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    // replace_key puts the key `entry` was called with into the map, in
+    // place of the one that's there, and returns the old one. That's only
+    // useful when keys can be equal but still tell apart, like an `Rc`.
+    //
+    // An entry from anywhere else, like `entry_ref`, `raw_entry_mut` or a
+    // vacant entry's `insert_entry`, has no key to swap in, so it returns
+    // None and leaves the key alone.
+    pub fn replace_key(self) -> Option<K> {
+        let key = self.key?;
+        Some(mem::replace(&mut self.map.node_mut(self.index).key, key))
+    }
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        let node = self.map.remove_found(self.bucket, self.index);
+        (node.key, node.value)
     }
 }
 
-pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
-    entry: &'a mut (K, V),
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for OccupiedEntry<'_, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+// OccupiedError is what `try_insert` hands back when the key is already
+// there. It has the entry that's in the way, and the value that wasn't
+// inserted, so nothing is lost.
+pub struct OccupiedError<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    pub entry: OccupiedEntry<'a, K, V, S>,
+    pub value: V,
 }
 
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for OccupiedError<'_, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.entry.get())
+            .field("new_value", &self.value)
+            .finish()
+    }
+}
 
-pub struct VacantEntry<'a, K: 'a, V: 'a> {
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Display for OccupiedError<'_, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to insert {:?}, key {:?} already exists with value {:?}",
+            self.value,
+            self.entry.key(),
+            self.entry.get(),
+        )
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> Error for OccupiedError<'_, K, V, S> {}
+
+pub struct VacantEntry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
     key: K,
-    map: &'a mut HashMap<K, V>,
-    bucket: usize,
+    map: &'a mut HashMap<K, V, S>,
+    hash: u64,
 }
 
-impl<'a, K: 'a, V: 'a> VacantEntry<'a, K, V> {
-    pub fn insert(self, value: V) -> &'a mut V
-    where
-        K: Hash + Eq,
-    {   
-        self.map.buckets[self.bucket].push((self.key, value));
-        self.map.items += 1;
-        &mut self.map.buckets[self.bucket].last_mut().unwrap().1
+impl<'a, K: 'a, V: 'a, S: 'a> VacantEntry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    // into_key gives back the key, without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
     }
 }
 
-pub enum Entry<'a, K: 'a, V: 'a> {
-    Occupied(OccupiedEntry<'a, K, V>),
-    Vacant(VacantEntry<'a, K, V>)
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn insert(self, value: V) -> &'a mut V {
+        let (map, index) = self.insert_index(value);
+        &mut map.node_mut(index).value
+    }
+
+    // insert_entry is the same as `insert`, but hands back the new entry
+    // rather than just its value.
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, K, V, S> {
+        let (map, index) = self.insert_index(value);
+
+        // Inserting can rehash, so we don't know which bucket it's in. It
+        // can reseed too, so the hash we had might not be its hash any more.
+        let hash = map.node(index).hash;
+        let (bucket, _) = map.find_by(hash, |i| i == index).expect("entry missing from buckets");
+        OccupiedEntry {
+            key: None,
+            map,
+            bucket,
+            index,
+        }
+    }
+
+    fn insert_index(self, value: V) -> (&'a mut HashMap<K, V, S>, usize) {
+        let index = self.map.alloc(self.hash, self.key, value);
+        // `entry` already took care of any migrating
+        let probed = self.map.buckets.insert(self.hash, index);
+        self.map.check_chain(probed);
+        self.map.evict_eldest(index);
+        (self.map, index)
+    }
+}
+
+impl<K: fmt::Debug, V, S> fmt::Debug for VacantEntry<'_, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+pub enum Entry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>)
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for Entry<'_, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Occupied(e) => f.debug_tuple("Entry").field(e).finish(),
+            Entry::Vacant(e) => f.debug_tuple("Entry").field(e).finish(),
+        }
+    }
 }
 
-impl<'a, K, V> Entry<'a, K, V> 
+impl<'a, K: 'a, V: 'a, S: 'a> Entry<'a, K, V, S> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    // and_modify runs `f` on the value if there is one, and hands the
+    // entry back so it can be chained with `or_insert` and friends.
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.get_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
     where
         K: Hash + Eq,
+        S: BuildHasher,
     {
     pub fn or_insert(self, value: V) -> &'a mut V {
         match self {
-            Entry::Occupied(e) => &mut e.entry.1, // .1 gets the value from a tuple
+            Entry::Occupied(e) => e.into_mut(),
             Entry::Vacant(e) => e.insert(value),
         }
     }
@@ -62,275 +720,670 @@ impl<'a, K, V> Entry<'a, K, V>
     // You only construct the item `F` if it needs to be inserted,
     // or_insert will insert whatever value you give it, so `Vec::new`
     // you will instantiate even if the value exists, and you can't insert a new one.
-    // or_insert_with, only creates the new constructor if it doesn't exist already, 
+    // or_insert_with, only creates the new constructor if it doesn't exist already,
     // and needs to be inserted.
     pub fn or_insert_with<F>(self, maker: F) -> &'a mut V
     where
         F: FnOnce() -> V
     {
         match self {
-            Entry::Occupied(e) => &mut e.entry.1,
+            Entry::Occupied(e) => e.into_mut(),
             Entry::Vacant(e) => e.insert(maker()),
         }
     }
 
-    pub fn or_default(self) -> &'a mut V
-    where
-        V: Default
-    {
-      self.or_insert_with(Default::default)
+    // or_insert_with_key is `or_insert_with`, but the maker gets to see
+    // the key.
+    pub fn or_insert_with_key<F>(self, maker: F) -> &'a mut V
+    where
+        F: FnOnce(&K) -> V,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let value = maker(e.key());
+                e.insert(value)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default
+    {
+      self.or_insert_with(Default::default)
+    }
+
+    // insert_entry sets the value, whether or not the key was there
+    // already, and hands back the entry.
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, K, V, S> {
+        match self {
+            Entry::Occupied(mut e) => {
+                e.insert(value);
+                e
+            }
+            Entry::Vacant(e) => e.insert_entry(value),
+        }
+    }
+}
+
+// EntryRef is what `entry_ref` hands back. It's the same as `Entry`, except
+// a missing key is only borrowed, and gets turned into an owned one if and
+// when something is actually inserted.
+pub enum EntryRef<'a, 'b, K: 'a, Q: ?Sized + 'b, V: 'a, S: 'a = RandomState> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntryRef<'a, 'b, K, Q, V, S>),
+}
+
+pub struct VacantEntryRef<'a, 'b, K: 'a, Q: ?Sized + 'b, V: 'a, S: 'a = RandomState> {
+    key: &'b Q,
+    map: &'a mut HashMap<K, V, S>,
+    hash: u64,
+}
+
+impl<'a, 'b, K: 'a, Q: ?Sized + 'b, V: 'a, S: 'a> VacantEntryRef<'a, 'b, K, Q, V, S> {
+    pub fn key(&self) -> &'b Q {
+        self.key
+    }
+}
+
+impl<'a, 'b, K, Q, V, S> VacantEntryRef<'a, 'b, K, Q, V, S>
+where
+    K: Hash + Eq,
+    Q: ?Sized,
+    S: BuildHasher,
+{
+    // insert converts the borrowed key into an owned one with `From`.
+    pub fn insert(self, value: V) -> &'a mut V
+    where
+        K: From<&'b Q>,
+    {
+        self.insert_with_key(K::from, value)
+    }
+
+    // insert_with_key is for keys which can't be made with `From`, it
+    // calls `make_key` to get the owned key. That has to hash and compare
+    // the same as the borrowed one, or the map won't be able to find it.
+    pub fn insert_with_key<F>(self, make_key: F, value: V) -> &'a mut V
+    where
+        F: FnOnce(&'b Q) -> K,
+    {
+        VacantEntry {
+            key: make_key(self.key),
+            map: self.map,
+            hash: self.hash,
+        }
+        .insert(value)
+    }
+}
+
+impl<'a, 'b, K: 'a, Q: ?Sized + 'b, V: 'a, S: 'a> EntryRef<'a, 'b, K, Q, V, S> {
+    pub fn key(&self) -> &Q
+    where
+        K: Borrow<Q>,
+    {
+        match self {
+            EntryRef::Occupied(e) => e.key().borrow(),
+            EntryRef::Vacant(e) => e.key(),
+        }
+    }
+
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        match self {
+            EntryRef::Occupied(mut e) => {
+                f(e.get_mut());
+                EntryRef::Occupied(e)
+            }
+            EntryRef::Vacant(e) => EntryRef::Vacant(e),
+        }
+    }
+}
+
+// The `or_insert` family might have to turn the borrowed key into an owned
+// one, so they're the only ones which need `From`.
+impl<'a, 'b, K, Q, V, S> EntryRef<'a, 'b, K, Q, V, S>
+where
+    K: Hash + Eq + Borrow<Q> + From<&'b Q>,
+    Q: ?Sized,
+    S: BuildHasher,
+{
+    pub fn or_insert(self, value: V) -> &'a mut V {
+        match self {
+            EntryRef::Occupied(e) => e.into_mut(),
+            EntryRef::Vacant(e) => e.insert(value),
+        }
+    }
+
+    pub fn or_insert_with<F>(self, maker: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            EntryRef::Occupied(e) => e.into_mut(),
+            EntryRef::Vacant(e) => e.insert(maker()),
+        }
+    }
+
+    pub fn or_insert_with_key<F>(self, maker: F) -> &'a mut V
+    where
+        F: FnOnce(&Q) -> V,
+    {
+        match self {
+            EntryRef::Occupied(e) => e.into_mut(),
+            EntryRef::Vacant(e) => {
+                let value = maker(e.key());
+                e.insert(value)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(Default::default)
+    }
+}
+
+// HashMap for keys which have an equality hash check trait
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        self.make_room();
+
+        let hash = self.hash(&key);
+        match self.find(hash, &key) {
+            Some((bucket, index)) => {
+                self.touch(index);
+                Entry::Occupied(OccupiedEntry {
+                    key: Some(key),
+                    map: self,
+                    bucket,
+                    index,
+                })
+            }
+            None => Entry::Vacant(VacantEntry { map: self, key, hash })
+        }
+    }
+
+    // try_insert inserts the key and value only if the key isn't there
+    // already. If it is, nothing changes, and the error holds the entry
+    // that's there and the value which wasn't inserted.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V, S>> {
+        match self.entry(key) {
+            Entry::Occupied(entry) => Err(OccupiedError { entry, value }),
+            Entry::Vacant(entry) => Ok(entry.insert(value)),
+        }
+    }
+
+    // entry_ref looks up a borrowed key, so `map.entry_ref(word)` only has
+    // to allocate a `String` when the word isn't in the map yet. Any key it
+    // does insert is built from the borrowed one, see `VacantEntryRef`.
+    pub fn entry_ref<'b, Q>(&mut self, key: &'b Q) -> EntryRef<'_, 'b, K, Q, V, S>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.make_room();
+
+        let hash = self.hash(key);
+        match self.find(hash, key) {
+            Some((bucket, index)) => {
+                self.touch(index);
+                EntryRef::Occupied(OccupiedEntry {
+                    key: None,
+                    map: self,
+                    bucket,
+                    index,
+                })
+            }
+            None => EntryRef::Vacant(VacantEntryRef { key, map: self, hash }),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.make_room();
+
+        let hash = self.hash(&key);
+
+        // Replacing the value of an existing key leaves it where it was in
+        // the insertion order, unless we're tracking access order.
+        if let Some((_, index)) = self.find(hash, &key) {
+            self.touch(index);
+            return Some(mem::replace(&mut self.node_mut(index).value, value));
+        }
+
+        let index = self.alloc(hash, key, value);
+        let probed = self.buckets.insert(hash, index);
+        self.check_chain(probed);
+        self.evict_eldest(index);
+        None
+    }
+
+    // make_room is called before anything which might add an entry, and
+    // grows the map if one more would take it over the resize policy's max
+    // load. Removed entries leave tombstones behind, which take up buckets
+    // just the same, so they count towards the load too.
+    fn make_room(&mut self) {
+        self.migrate(MIGRATE_STEP);
+        let used = self.items + self.buckets.tombstones();
+        if used >= self.resize_policy.max_items(self.buckets.nbuckets()) {
+            self.resize();
+        }
+    }
+
+    // set_resize_policy changes when the map grows and shrinks, and what
+    // sizes it uses. That takes effect the next time the map resizes, unless
+    // it changes the kind of table size, which means rebuilding the table
+    // straight away.
+    pub fn set_resize_policy(&mut self, policy: ResizePolicy) {
+        let table_size = self.resize_policy.table_size;
+        self.resize_policy = policy;
+        if policy.table_size != table_size && self.buckets.nbuckets() > 0 {
+            let nbuckets = policy.buckets_for(self.items).expect("capacity overflow");
+            self.rehash(nbuckets);
+        }
+    }
+
+    // evict_eldest asks the remove eldest policy, if there is one, whether
+    // to remove the entry at the front, until it says no or we reach the
+    // entry which was just inserted.
+    fn evict_eldest(&mut self, inserted: usize) {
+        while self.head.get() != inserted {
+            let policy = match self.remove_eldest.as_ref() {
+                Some(policy) => policy,
+                None => return,
+            };
+
+            // A policy which panicked last time can't have left the map in
+            // a mess, so there's no need to give up on it.
+            let mut policy = policy.lock().unwrap_or_else(PoisonError::into_inner);
+            let eldest = self.entries[self.head.get()].as_ref().expect("linked entry is vacant");
+            if !policy.remove_eldest(self.items, &eldest.key, &eldest.value) {
+                return;
+            }
+            drop(policy);
+            self.pop_front();
+        }
+    }
+
+    // set_incremental_resize turns incremental resizing on or off, see
+    // `old_buckets`. It trades a little speed on every call for never
+    // having one insert stall while the whole table is moved.
+    pub fn set_incremental_resize(&mut self, incremental: bool) {
+        self.incremental_resize = incremental;
+        if !incremental {
+            self.migrate(usize::MAX);
+        }
+    }
+
+    // reserve makes room for at least `additional` more entries, so they can
+    // be inserted without resizing.
+    pub fn reserve(&mut self, additional: usize) {
+        if let Err(err) = self.try_reserve(additional) {
+            match err {
+                TryReserveError::CapacityOverflow => panic!("capacity overflow"),
+                TryReserveError::AllocError => panic!("memory allocation failed"),
+            }
+        }
+    }
+
+    // try_reserve is the same as `reserve`, but hands back an error rather
+    // than panicking if there isn't enough memory.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let needed = self
+            .items
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
+
+        // Vec doesn't say which of the two went wrong, so check the size
+        // of the entries ourselves before asking it for the memory.
+        let slots = additional.saturating_sub(self.free.len());
+        let bytes = self
+            .entries
+            .len()
+            .checked_add(slots)
+            .and_then(|n| n.checked_mul(mem::size_of::<Option<Node<K, V>>>()))
+            .ok_or(TryReserveError::CapacityOverflow)?;
+        if bytes > isize::MAX as usize {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+        self.entries
+            .try_reserve(slots)
+            .map_err(|_| TryReserveError::AllocError)?;
+
+        if needed > self.capacity() {
+            let nbuckets = self
+                .resize_policy
+                .buckets_for(needed)
+                .ok_or(TryReserveError::CapacityOverflow)?;
+            let buckets = RawTable::try_with_buckets(nbuckets, self.resize_policy.table_size)?;
+            self.rehash_into(buckets);
+        }
+        Ok(())
+    }
+
+    // shrink_to_fit gives back as much memory as it can, leaving only
+    // enough room for the entries already in the map.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    // shrink_to gives back memory, but keeps enough room for at least
+    // `min_capacity` entries. It never grows the map.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let capacity = min_capacity.max(self.items);
+        let nbuckets = self
+            .resize_policy
+            .buckets_for(capacity)
+            .expect("capacity overflow")
+            .min(self.buckets.nbuckets());
+        let entries_capacity = capacity.min(self.entries.capacity());
+        if nbuckets >= self.buckets.nbuckets() && entries_capacity >= self.entries.capacity() {
+            return;
+        }
+
+        // Removed entries leave holes in `entries`, so close those up, and
+        // then put everything into buckets of the right size.
+        if entries_capacity < self.entries.capacity() {
+            self.compact(entries_capacity);
+        }
+        self.rehash(nbuckets);
+    }
+
+    // @todo - look-up Amortised costs?
+    // resize -
+    fn resize(&mut self) {
+        // Decides how many buckets to create, given the amount of current
+        // buckets, which is up to the resize policy. If most of the buckets
+        // are tombstones though, clearing them out makes enough room.
+        let nbuckets = self.buckets.nbuckets();
+        let needed = self.items + 1;
+        let target_size = if self.buckets.tombstones() > self.items
+            && self.resize_policy.max_items(nbuckets) >= needed
+        {
+            nbuckets
+        } else {
+            self.resize_policy.grow(nbuckets, needed).expect("capacity overflow")
+        };
+        self.resize_to(target_size);
     }
-}
 
-// HashMap for keys which have an equality hash check trait
-impl<K, V> HashMap<K, V> 
-where
-    K: Hash + Eq,
-{
-    pub fn entry<'a>(&'a mut self, key: K) -> Entry<'a, K, V> {
-        if self.buckets.is_empty() || self.items > 3 * self.buckets.len() / 4 {
-            self.resize();
+    // shrink_if_sparse is called after a remove, and shrinks the table if
+    // the resize policy says it's got empty enough.
+    fn shrink_if_sparse(&mut self) {
+        if let Some(target_size) = self.resize_policy.shrink(self.buckets.nbuckets(), self.items) {
+            self.resize_to(target_size);
         }
+    }
+
+    fn resize_to(&mut self, target_size: usize) {
+        // Only one resize at a time, so finish off the last one first.
+        self.migrate(usize::MAX);
 
-        let bucket = self.bucket(&key);
-        match self.buckets[bucket].iter().position(|&(ref ekey, _)| ekey == &key) {
-            Some(index) => Entry::Occupied(OccupiedEntry {
-                entry: &mut self.buckets[bucket][index]
-            }),
-            None => Entry::Vacant(VacantEntry { map: self, key, bucket })
+        if self.incremental_resize {
+            let new_buckets = RawTable::with_buckets(target_size, self.resize_policy.table_size);
+            self.old_buckets = Some(mem::replace(&mut self.buckets, new_buckets));
+            self.migrated = 0;
+        } else {
+            self.rehash(target_size);
         }
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-
-        // If the buckets are empty, or the items are greater than the number of buckets,
-        // divided by 4, then resize.
-        //
-        // Meaning we will always attempt to resize the buckets, if there are more items
-        // than a quarter of the amount of buckets. Meaning there will always be four as many 
-        // items as buckets.
-        //
-        // This is kind of arbitrary, but if you had say, a bucket per item, it would use loads
-        // of memory. Whereas, if you had one bucket for all items, it would take ages to 
-        // traverse all of the items in a bucket.
-        if self.buckets.is_empty() || self.items > self.buckets.len() / 4 {
-            self.resize(); 
-        } 
-
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        let bucket = self.bucket(&key);
-        let bucket = &mut self.buckets[bucket];
-        
+    // migrate moves up to `nbuckets` of the old buckets across to the new
+    // ones, if there's an incremental resize going on.
+    fn migrate(&mut self, nbuckets: usize) {
+        let mut old = match self.old_buckets.take() {
+            Some(old) => old,
+            None => return,
+        };
 
-        for &mut (ref ekey, ref mut evalue) in bucket.iter_mut() {
-            if ekey == &key {
-                return Some(mem::replace(evalue, value));
+        let end = self.migrated.saturating_add(nbuckets).min(old.nbuckets());
+        for at in self.migrated..end {
+            // They have to actually be removed from the old buckets, rather
+            // than just skipped over, or we'd find them there again after
+            // they're removed from the new ones.
+            if let Some(index) = old.get(at) {
+                old.remove(at);
+                self.buckets.insert(self.node(index).hash, index);
             }
         }
 
-        
-        self.items += 1;
-        bucket.push((key, value));
-        None
+        self.migrated = end;
+        if end < old.nbuckets() {
+            self.old_buckets = Some(old);
+        }
     }
 
-    // @todo - look-up Amortised costs? 
-    // resize - 
-    fn resize(&mut self) {
+    // rehash puts every entry into a fresh set of buckets.
+    fn rehash(&mut self, target_size: usize) {
+        self.rehash_into(RawTable::with_buckets(target_size, self.resize_policy.table_size));
+    }
 
-        // Decides how many buckets to create, given the amount of
-        // current buckets. It pretty much just doubles them, unless
-        // it's 0, then it uses a default value.
-        let target_size = match self.buckets.len() {
-            0 => INITIAL_NBUCKETS,
-            n => 2 * n,
-        };
+    fn rehash_into(&mut self, mut new_buckets: RawTable) {
+        // Go through the slots and fill the new buckets up again. That's
+        // quicker than following the links, and as the entries themselves
+        // stay where they are, the order is untouched.
+        for (index, slot) in self.entries.iter().enumerate() {
+            if let Some(node) = slot {
+                new_buckets.insert(node.hash, index);
+            }
+        }
 
-        // Create a new vector of empty buckets with the given target size
-        let mut new_buckets = Vec::with_capacity(target_size);
+        // In memory replacement of the old and new buckets list, every entry
+        // is in the new buckets, so there's nothing left to migrate.
+        self.buckets = new_buckets;
+        self.old_buckets = None;
+    }
 
-        // Fill the new buckets with empty items to be re-populated
-        new_buckets.extend((0..target_size).map(|_| Vec::new()));
+    // check_chain is called with how many groups an insert had to probe
+    // past. With a decent hasher and random keys, having to go this far is
+    // vanishingly unlikely, so it probably means someone has found a bunch of keys which
+    // collide. Picking a new salt and rehashing breaks those collisions up.
+    //
+    // If the hasher ignores the salt, or is just plain bad, rehashing won't
+    // help, so we only try once per table size, rather than on every insert.
+    //
+    // Still having long chains after reseeding most likely means a hasher
+    // which leaves the low bits of its hashes the same, like the identity
+    // hash of an aligned pointer. Fibonacci hashing copes with that, so if
+    // we're just masking off the low bits, we switch over to it.
+    fn check_chain(&mut self, probed: usize) {
+        if probed <= self.collision_threshold {
+            return;
+        }
 
-        // Drain the old buckets and fill the new ones up again
-        for (key, value) in self.buckets.iter_mut().flat_map(|bucket| bucket.drain(..)) {
-            let mut hasher = DefaultHasher::new();
-            key.hash(&mut hasher);
+        let nbuckets = self.buckets.nbuckets();
+        self.collision_stats.long_chains += 1;
+        if self.reseeded_at != nbuckets {
+            self.reseeded_at = nbuckets;
+            self.collision_stats.reseeds += 1;
+            self.salt = RandomState::new().hash_one(self.salt);
 
-            // @todo - I don't fully understand this, I probaby need to see what
-            // hasher returns, to figure out why the modulus of hasher.finish,
-            // becomes the new bucket
-            let bucket = (hasher.finish() % new_buckets.len() as u64) as usize;
-            new_buckets[bucket].push((key, value));
+            // The salt is the one thing which changes the hashes, so this
+            // is the only time they need working out again.
+            let mut index = self.head.get();
+            while index != NIL {
+                let hash = self.hash(&self.node(index).key);
+                let node = self.node_mut(index);
+                node.hash = hash;
+                index = node.next.get();
+            }
+            self.rehash(nbuckets);
+        } else if self.resize_policy.table_size == TableSize::PowerOfTwo {
+            self.resize_policy.table_size = TableSize::Fibonacci;
+            self.rehash(nbuckets);
         }
-
-        // In memory replacement of the old and new buckets list
-        mem::replace(&mut self.buckets, new_buckets);
     }
 
-    // bucket is a convenience method for figuring out the 
-    // bucket for a given key
-    fn bucket<Q>(&self, key: &Q) -> usize
+    // hash runs a key through a fresh hasher from the map's hash builder.
+    fn hash<Q>(&self, key: &Q) -> u64
     where
-      K: Borrow<Q>,
-      Q: Hash + Eq + ?Sized,
+      Q: Hash + ?Sized,
     {
-        let mut hasher = DefaultHasher::new();
+        let mut hasher = self.hash_builder.build_hasher();
+        if self.salt != 0 {
+            hasher.write_u64(self.salt);
+        }
         key.hash(&mut hasher);
-        (hasher.finish() % self.buckets.len() as u64) as usize
+        hasher.finish()
     }
 
-    pub fn len(&self) -> usize {
-        self.items
+    // find looks for a key with the given hash, and returns the bucket it's
+    // in, along with the index of its entry.
+    fn find<Q>(&self, hash: u64, key: &Q) -> Option<(Bucket, usize)>
+    where
+      Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let entries = &self.entries;
+        self.find_by(hash, |index| match entries[index] {
+            Some(ref node) => node.hash == hash && key.equivalent(&node.key),
+            None => false,
+        })
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.items == 0
-    } 
+    // find_by looks in the current buckets, then the old ones, for the
+    // entry that `eq` accepts.
+    fn find_by<F>(&self, hash: u64, mut eq: F) -> Option<(Bucket, usize)>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        if let Some(at) = self.buckets.find(hash, &mut eq) {
+            return Some((Bucket::Current(at), self.buckets.index(at)));
+        }
+
+        let old = self.old_buckets.as_ref()?;
+        let at = old.find(hash, eq)?;
+        Some((Bucket::Old(at), old.index(at)))
+    }
 
+    fn remove_bucket(&mut self, bucket: Bucket) {
+        match bucket {
+            Bucket::Current(at) => self.buckets.remove(at),
+            Bucket::Old(at) => self
+                .old_buckets
+                .as_mut()
+                .expect("no old buckets to remove from")
+                .remove(at),
+        };
+    }
+
+    // get only has `&self`, so it can't help an incremental resize along
+    // like the other methods do, it just looks in both sets of buckets. In
+    // access order it still moves the entry to the back, see `touch`.
+    //
+    // None of the lookups, `get`, `peek`, `get_refresh`, `contains_key`,
+    // `remove`, the `raw_entry` ones, or anything on `iter`, ever panic on a
+    // map which hasn't allocated anything yet. A table with no buckets just
+    // finds nothing (see `RawTable::find`), and nothing is sized by dividing
+    // by the number of buckets. Only `Index` panics, on a missing key.
     pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
-      K: Borrow<Q>,
-      Q: Hash + Eq + ?Sized, // ?Sized means Q can be str, which isn't sized
+      Q: Hash + Equivalent<K> + ?Sized, // ?Sized means Q can be str, which isn't sized
     {
-        self.buckets[self.bucket(key)]
-          .iter()
-          .find(|&(ref ekey, _)| ekey.borrow() == key)
-          .map(|&(_, ref v)| v)
+        let (_, index) = self.find(self.hash(key), key)?;
+        self.touch(index);
+        Some(&self.node(index).value)
     }
 
-    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    // peek is `get`, but never moves anything, even in access order.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized, // ?Sized means Q can be str, which isn't sized
+        Q: Hash + Equivalent<K> + ?Sized,
     {
-        let bucket = self.bucket(key);
-        let bucket = &mut self.buckets[bucket];
-
-        // The ? operator with an Option return type, returns a None type immediately if false,
-        // whereas with a Result return type, it returns an Err type.
-        let i = bucket.iter().position(|&(ref ekey, _)| ekey.borrow() == key)?;
-
-        self.items -= 1;
-
-        // Swap remove, the following case vec![a, b, c, d, e] swap_remove(a, e), would swap,
-        // a and e in place, which is more efficient than removing a, then adding the new value
-        // onto the end of the vector. Which means you'd end up with vec![e, b, c] etc, which
-        // is fine if you do not need your vec to be ordered. Our buckets are not ordered here,
-        // so this is fine in this case.
-        Some(bucket.swap_remove(i).1)
+        let (_, index) = self.find(self.hash(key), key)?;
+        Some(&self.node(index).value)
     }
 
-    // contains_key - checks keys and returns true or false if exists
-    pub fn contains_key<Q>(&mut self, key: &Q) -> bool
+    // get_refresh looks up a key like `get`, but also moves the entry to the
+    // back of the map, whether or not the map is in access order.
+    pub fn get_refresh<Q>(&mut self, key: &Q) -> Option<&mut V>
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq + ?Sized, // ?Sized means Q can be str, which isn't sized
+        Q: Hash + Equivalent<K> + ?Sized,
     {
-        self.get(key).is_some()
-    }
-}
-
-pub struct Iter<'a, K, V> {
-    map: &'a HashMap<K, V>,
-    bucket: usize, // Call store iterators in the buckets themselves? @todo look this up
-    at: usize,
-    // Could have a yield cound here to prevent 'over yielding'
-}
-
-impl <'a, K, V> Iterator for Iter<'a, K, V> {
-    type Item = (&'a K, &'a V);
-
-    fn next(&mut self) -> Option<Self::Item> {
-
-        // We use a loop here to act as tail call elimination
-        // the loop just iterates against a match, which increments
-        // the current bucket, and current item position.
-        loop {
-          match self.map.buckets.get(self.bucket) {
-              Some(bucket) => {
-                  match bucket.get(self.at) {
-                      Some(&(ref k, ref v)) => {
-                          self.at += 1;
-                          break Some((k, v));
-                      }
-                      None => {
-                          // We've reached the end of the bucket in this case
-                          // So we move on to the next bucket, and set the
-                          // current position to zero again.
-                          self.bucket += 1;
-                          self.at = 0;
-                          continue;
-                      }
-                  }
-              }
-
-              // No more items
-              None => break None,
-            };
-        }
+        self.migrate(MIGRATE_STEP);
+        let (_, index) = self.find(self.hash(key), key)?;
+        self.move_back(index);
+        Some(&mut self.node_mut(index).value)
     }
-}
 
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized, // ?Sized means Q can be str, which isn't sized
+    {
+        self.migrate(MIGRATE_STEP);
 
-impl<'a, K, V> IntoIterator for &'a HashMap<K, V> {
-    type Item = (&'a K, &'a V);
-    type IntoIter = Iter<'a, K, V>;
-    fn into_iter(self) -> Self::IntoIter {
-        Iter {
-            map: self,
-            bucket: 0,
-            at: 0,
-        }
+        // The ? operator with an Option return type, returns a None type immediately if false,
+        // whereas with a Result return type, it returns an Err type.
+        let (bucket, index) = self.find(self.hash(key), key)?;
+        Some(self.remove_found(bucket, index).value)
     }
-}
 
-pub struct IntoIter<K, V> {
-    map: HashMap<K, V>,
-    bucket: usize,
-}
+    // remove_found removes an entry that `find` found.
+    fn remove_found(&mut self, bucket: Bucket, index: usize) -> Node<K, V> {
+        self.remove_bucket(bucket);
+        let node = self.release(index);
+        self.shrink_if_sparse();
+        node
+    }
 
-impl<K, V> Iterator for IntoIter<K, V> {
-    type Item = (K, V);
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.map.buckets.get_mut(self.bucket) {
-                Some(bucket) => match bucket.pop() {
-                    Some(x) => break Some(x),
-                    None => {
-                        self.bucket += 1;
-                        continue;
-                    }
-                },
-                None => break None,
+    // remove_index removes the entry at an index, which we've got to by
+    // following the links rather than looking up a key.
+    fn remove_index(&mut self, index: usize) -> Node<K, V> {
+        let hash = self.node(index).hash;
+        let (bucket, _) = self.find_by(hash, |i| i == index).expect("entry missing from buckets");
+        self.remove_bucket(bucket);
+        self.release(index)
+    }
+
+    // retain removes every entry `keep` returns false for, leaving the rest
+    // in the same order. Each entry is removed before `keep` is called for
+    // the next one, so the map is still in one piece if it panics.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut index = self.head.get();
+        while index != NIL {
+            let node = self.node_mut(index);
+            let next = node.next.get();
+            if !keep(&node.key, &mut node.value) {
+                self.remove_index(index);
             }
+            index = next;
         }
+        self.shrink_if_sparse();
     }
-}
-
 
-impl<K, V> IntoIterator for HashMap<K, V> {
-    type Item = (K, V);
-    type IntoIter = IntoIter<K, V>;
-    fn into_iter(self) -> Self::IntoIter {
-        IntoIter {
-            map: self,
-            bucket: 0,
-        }
+    // contains_key - checks keys and returns true or false if exists. It
+    // doesn't count as an access, so never moves anything.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized, // ?Sized means Q can be str, which isn't sized
+    {
+        self.peek(key).is_some()
     }
 }
 
 use std::iter::FromIterator;
-impl<K, V> FromIterator<(K, V)> for HashMap<K, V>
+impl<K, V, S> FromIterator<(K, V)> for HashMap<K, V, S>
 where
     K: Hash + Eq,
+    S: BuildHasher + Default,
 {
     fn from_iter<I>(iter: I) -> Self
     where
         I: IntoIterator<Item = (K, V)>,
     {
-        let mut map = HashMap::new();
+        // Size the map up front for however many entries the iterator
+        // says it has at least.
+        let iter = iter.into_iter();
+        let mut map = HashMap::with_capacity_and_hasher(iter.size_hint().0, S::default());
         for (k, v) in iter {
             map.insert(k, v);
         }
@@ -380,4 +1433,657 @@ mod tests {
 
         assert_eq!((&map).into_iter().count(), 5);
     }
+
+    #[test]
+    fn insertion_order() {
+        // Enough entries to force several resizes along the way
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i, i * 2);
+        }
+
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, (0..100).collect::<Vec<_>>());
+
+        // Re-inserting keeps the original position, removing closes the gap
+        assert_eq!(map.insert(0, 1), Some(0));
+        assert_eq!(map.remove(&50), Some(100));
+        map.insert(50, 100);
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys[0], 0);
+        assert_eq!(keys[50], 51);
+        assert_eq!(keys[99], 50);
+        assert_eq!(map.get(&0), Some(&1));
+    }
+
+    #[test]
+    fn access_order() {
+        let mut map = HashMap::with_access_order(true);
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.insert("c", 3);
+        map.insert("d", 4);
+
+        assert_eq!(map.get(&"a"), Some(&1));
+        assert_eq!(map.insert("b", 20), Some(2));
+        *map.entry("c").or_insert(0) += 10;
+        assert_eq!(map.get_refresh(&"a"), Some(&mut 1));
+
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["d", "b", "c", "a"]);
+
+        // peek and contains_key don't count as using an entry, get does
+        assert_eq!(map.peek(&"d"), Some(&4));
+        assert!(map.contains_key(&"b"));
+        assert_eq!(map.get(&"c"), Some(&13));
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["d", "b", "a", "c"]);
+
+        // Moving entries about in the middle of iterating can't go wrong,
+        // it just might see some entries twice and others not at all.
+        let seen = map.iter().inspect(|(k, _)| assert!(map.get(*k).is_some())).count();
+        assert!(seen <= map.len());
+
+        // Any hasher can be in access order
+        let mut map = HashMap::with_hasher(RandomState::with_seeds(1, 2));
+        map.set_access_order(true);
+        map.extend([("a", 1), ("b", 2)]);
+        map.get(&"a");
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["b", "a"]);
+
+        // Without access order only get_refresh moves entries
+        let mut map = HashMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.insert("a", 3);
+        map.entry("a").or_insert(0);
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["a", "b"]);
+        map.get_refresh(&"a");
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn remove_eldest() {
+        let mut map = HashMap::new();
+        map.set_remove_eldest(|len, _: &_, _: &_| len > 3);
+        for i in 0..10 {
+            map.insert(i, i);
+        }
+        *map.entry(10).or_insert(0) += 10;
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec![8, 9, 10]);
+        assert_eq!(map.get(&10), Some(&10));
+
+        // Updating an existing key isn't an insert, so nothing is evicted
+        map.set_remove_eldest(|_, _: &_, _: &_| true);
+        map.insert(8, 80);
+        assert_eq!(map.len(), 3);
+
+        // The entry which was just inserted always survives
+        map.insert(11, 11);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&11, &11)]);
+
+        // Age based, treating the values as timestamps and throwing away
+        // everything older than 5
+        let mut map = HashMap::new();
+        map.set_remove_eldest(|_, _: &&str, &stamp: &u32| stamp < 5);
+        map.insert("a", 5);
+        map.insert("b", 6);
+        assert_eq!(map.len(), 2);
+        map.insert("c", 7);
+        assert_eq!(map.len(), 3);
+
+        // Until the cut off moves on
+        map.set_remove_eldest(|_, _: &&str, &stamp: &u32| stamp < 7);
+        map.insert("d", 8);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&"c", &7), (&"d", &8)]);
+
+        // Or one picked at runtime
+        let policy: Box<dyn RemoveEldest<&str, u32> + Send> = if map.len() > 1 {
+            Box::new(|len, _: &_, _: &_| len > 1)
+        } else {
+            Box::new(|_, _: &_, _: &_| false)
+        };
+        map.set_remove_eldest(policy);
+        map.insert("e", 9);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&"e", &9)]);
+
+        // Anything Send will do, it doesn't have to be Sync or Clone
+        let evicted = std::cell::Cell::new(0);
+        map.set_remove_eldest(move |_, _: &_, _: &_| {
+            evicted.set(evicted.get() + 1);
+            evicted.get() < 2
+        });
+        map.insert("f", 10);
+        map.insert("g", 11);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&"f", &10), (&"g", &11)]);
+    }
+
+    #[test]
+    fn custom_hasher() {
+        use std::hash::BuildHasherDefault;
+
+        // A hasher which sends every key to the same bucket, the map
+        // should still work, just slowly.
+        #[derive(Default)]
+        struct Collide;
+
+        impl Hasher for Collide {
+            fn finish(&self) -> u64 {
+                0
+            }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut map: HashMap<_, _, BuildHasherDefault<Collide>> = HashMap::default();
+        for i in 0..20 {
+            *map.entry(i).or_default() += i;
+        }
+        assert_eq!(map.remove(&7), Some(7));
+        assert_eq!(map.get(&8), Some(&8));
+        assert_eq!(map.len(), 19);
+
+        let mut map: HashMap<_, _, BuildHasherDefault<Collide>> =
+            HashMap::with_capacity_and_hasher(40, BuildHasherDefault::default());
+        map.set_collision_threshold(1);
+        let buckets = map.buckets.nbuckets();
+        let map = (0..40).fold(map, |mut map, i| {
+            map.insert(i, i);
+            map
+        });
+        assert_eq!(map.buckets.nbuckets(), buckets);
+
+        // Every key collides, so reseeding can't help, but we should only
+        // have tried once for each size of table
+        let stats = map.collision_stats();
+        assert!(stats.long_chains > 1);
+        assert_eq!(stats.reseeds, 1);
+    }
+
+    #[test]
+    fn poor_hasher() {
+        use std::hash::BuildHasherDefault;
+
+        // Hashes a key to itself, ignoring the salt, the same as hashing
+        // a pointer by its address would.
+        #[derive(Default)]
+        struct Identity(u64);
+
+        impl Hasher for Identity {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+            fn write(&mut self, bytes: &[u8]) {
+                for &b in bytes {
+                    self.0 = self.0 << 8 | u64::from(b);
+                }
+            }
+            fn write_u64(&mut self, n: u64) {
+                self.0 = n;
+            }
+        }
+
+        // Keys 4096 apart all land on the same bucket with a mask
+        let mut map: HashMap<u64, u64, BuildHasherDefault<Identity>> = HashMap::default();
+        for i in 0..1000 {
+            map.insert(i << 12, i);
+        }
+        assert_eq!(map.resize_policy().table_size, TableSize::Fibonacci);
+        assert!((0..1000).all(|i| map.get(&(i << 12)) == Some(&i)));
+
+        let mut map: HashMap<u64, u64, BuildHasherDefault<Identity>> = HashMap::default();
+        map.set_resize_policy(ResizePolicy::new().table_size(TableSize::Fibonacci));
+        for i in 0..1000 {
+            map.insert(i << 12, i);
+        }
+        assert_eq!(map.collision_stats(), CollisionStats::default());
+        assert!((0..1000).all(|i| map.get(&(i << 12)) == Some(&i)));
+    }
+
+    #[test]
+    fn reseed_long_chains() {
+        // With no threshold at all, any insert which spills over into a
+        // second group counts, which is bound to happen with the map 3/4 full
+        let mut map = HashMap::with_seed(1, 2);
+        map.set_collision_threshold(0);
+        for i in 0..1000 {
+            map.entry(i).or_insert(i);
+        }
+
+        let stats = map.collision_stats();
+        assert!(stats.reseeds > 0);
+        assert!(stats.long_chains >= stats.reseeds);
+        assert_ne!(map.salt, 0);
+        assert!((0..1000).all(|i| map.get(&i) == Some(&i)));
+        assert_eq!(map.iter().map(|(&k, _)| k).collect::<Vec<_>>(), (0..1000).collect::<Vec<_>>());
+
+        // insert_entry has to find the entry again after it's been reseeded
+        let mut map = HashMap::with_seed(1, 2);
+        map.set_collision_threshold(0);
+        for i in 0..1000 {
+            match map.entry(i) {
+                Entry::Vacant(e) => assert_eq!(e.insert_entry(i).get(), &i),
+                Entry::Occupied(_) => unreachable!(),
+            }
+        }
+        assert!(map.collision_stats().reseeds > 0);
+        assert!((0..1000).all(|i| map.get(&i) == Some(&i)));
+    }
+
+    #[test]
+    fn cached_hashes() {
+        use std::cell::Cell;
+
+        thread_local!(static HASHED: Cell<usize> = const { Cell::new(0) });
+
+        // Counts how many times any key has been hashed
+        #[derive(PartialEq, Eq)]
+        struct Key(u32);
+
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                HASHED.with(|n| n.set(n.get() + 1));
+                self.0.hash(state);
+            }
+        }
+
+        let mut map = HashMap::new();
+        for i in 0..1000 {
+            map.insert(Key(i), i);
+            map.remove(&Key(i / 2));
+        }
+        map.shrink_to_fit();
+
+        // One hash for each insert and remove, however many resizes
+        assert_eq!(HASHED.with(Cell::get), 2000);
+        assert!(map.get(&Key(999)).is_some());
+    }
+
+    #[test]
+    fn entry_api() {
+        use std::rc::Rc;
+
+        let mut map = HashMap::new();
+        for word in "the cat sat on the mat the end".split(' ') {
+            map.entry(word).and_modify(|n| *n += 1).or_insert(1);
+        }
+        assert_eq!(map.get(&"the"), Some(&3));
+        assert_eq!(map.entry("cat").key(), &"cat");
+        assert_eq!(*map.entry("dog").or_insert_with_key(|k| k.len()), 3);
+
+        match map.entry("sat") {
+            Entry::Occupied(mut e) => {
+                assert_eq!(e.key(), &"sat");
+                assert_eq!(e.insert(10), 1);
+                *e.get_mut() += 1;
+                assert_eq!(e.get(), &11);
+                assert_eq!(e.remove_entry(), ("sat", 11));
+            }
+            Entry::Vacant(_) => unreachable!(),
+        }
+        match map.entry("sat") {
+            Entry::Vacant(e) => {
+                assert_eq!(e.key(), &"sat");
+                assert_eq!(e.into_key(), "sat");
+            }
+            Entry::Occupied(_) => unreachable!(),
+        }
+
+        // The order is untouched by updating, and removing closes the gap
+        let e = map.entry("on").insert_entry(5);
+        assert_eq!(e.get(), &5);
+        assert_eq!(e.remove(), 5);
+        let e = match map.entry("rug") {
+            Entry::Vacant(e) => e.insert_entry(7),
+            Entry::Occupied(_) => unreachable!(),
+        };
+        assert_eq!(e.key(), &"rug");
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["the", "cat", "mat", "end", "dog", "rug"]);
+
+        let mut map = HashMap::new();
+        let a = Rc::new("a".to_string());
+        map.insert(a.clone(), 1);
+        let b = Rc::new("a".to_string());
+        match map.entry(b.clone()) {
+            Entry::Occupied(e) => assert!(Rc::ptr_eq(&e.replace_key().unwrap(), &a)),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert!(Rc::ptr_eq(map.iter().next().unwrap().0, &b));
+
+        // Entries which weren't given a key have nothing to replace it with
+        let key = "a".to_string();
+        match map.entry_ref(&key) {
+            EntryRef::Occupied(e) => assert_eq!(e.replace_key(), None),
+            EntryRef::Vacant(_) => unreachable!(),
+        }
+        match map.raw_entry_mut().from_key(&key) {
+            RawEntryMut::Occupied(e) => assert_eq!(e.replace_key(), None),
+            RawEntryMut::Vacant(_) => unreachable!(),
+        }
+        let c = Rc::new("c".to_string());
+        assert_eq!(map.entry(c.clone()).insert_entry(3).replace_key(), None);
+        assert!(Rc::ptr_eq(map.iter().next().unwrap().0, &b));
+        assert!(Rc::ptr_eq(map.back().unwrap().0, &c));
+    }
+
+    #[test]
+    fn entry_ref() {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for word in "the cat sat on the mat the end".split(' ') {
+            *counts.entry_ref(word).or_default() += 1;
+        }
+        assert_eq!(counts.get("the"), Some(&3));
+        assert_eq!(counts.len(), 6);
+        assert_eq!(counts.entry_ref("cat").key(), "cat");
+
+        match counts.entry_ref("dog") {
+            EntryRef::Vacant(e) => {
+                assert_eq!(e.key(), "dog");
+                e.insert_with_key(|k| k.to_owned(), 1);
+            }
+            EntryRef::Occupied(_) => unreachable!(),
+        }
+        assert_eq!(counts.get("dog"), Some(&1));
+
+        match counts.entry_ref("end").and_modify(|n| *n += 1) {
+            EntryRef::Occupied(e) => assert_eq!(e.remove_entry(), ("end".to_string(), 2)),
+            EntryRef::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(*counts.entry_ref("mat").or_insert_with_key(|k| k.len()), 1);
+        assert_eq!(*counts.entry_ref("rug").or_insert_with_key(|k| k.len()), 3);
+        let keys: Vec<_> = counts.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["the", "cat", "sat", "on", "mat", "dog", "rug"]);
+
+        // Looking and modifying works for keys which can't be made from a
+        // borrowed one, like a u64 from a &u64
+        let mut map: HashMap<u64, u64> = HashMap::from([(1, 10)]);
+        assert_eq!(map.entry_ref(&1).key(), &1);
+        match map.entry_ref(&1).and_modify(|v| *v += 1) {
+            EntryRef::Occupied(e) => assert_eq!(e.get(), &11),
+            EntryRef::Vacant(_) => unreachable!(),
+        }
+        match map.entry_ref(&2).and_modify(|v| *v += 1) {
+            EntryRef::Vacant(e) => e.insert_with_key(|&k| k, 20),
+            EntryRef::Occupied(_) => unreachable!(),
+        };
+        assert_eq!(map.get(&2), Some(&20));
+    }
+
+    #[test]
+    fn equivalent() {
+        // Hashes the same as a `(String, u32)`, without owning the string
+        #[derive(Hash)]
+        struct Pair<'a>(&'a str, u32);
+
+        impl Equivalent<(String, u32)> for Pair<'_> {
+            fn equivalent(&self, key: &(String, u32)) -> bool {
+                self.0 == key.0 && self.1 == key.1
+            }
+        }
+
+        let mut map = HashMap::new();
+        map.insert(("a".to_string(), 1), "a1");
+        map.insert(("a".to_string(), 2), "a2");
+        assert_eq!(map.get(&Pair("a", 2)), Some(&"a2"));
+        assert_eq!(map.get(&Pair("b", 2)), None);
+        assert!(map.contains_key(&Pair("a", 1)));
+        assert_eq!(map.remove(&Pair("a", 1)), Some("a1"));
+        assert_eq!(map.get_refresh(&Pair("a", 2)), Some(&mut "a2"));
+        assert_eq!(map.len(), 1);
+
+        // Borrowed keys still work the same as ever
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 1);
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.remove("a"), Some(1));
+    }
+
+    #[test]
+    fn seeded() {
+        let mut a = HashMap::with_seed(1, 2);
+        let mut b = HashMap::with_seed(1, 2);
+        for i in 0..50 {
+            a.insert(i, ());
+            b.insert(i, ());
+        }
+        assert_eq!(a.buckets, b.buckets);
+        assert_eq!(a.hasher(), b.hasher());
+        assert_ne!(HashMap::<u8, ()>::new().hasher(), HashMap::<u8, ()>::new().hasher());
+    }
+
+    #[test]
+    fn matches_std() {
+        // Throw a long run of inserts and removes at both maps, from a
+        // small pool of keys so there are plenty of hits and misses.
+        for &incremental in &[false, true] {
+            let mut ours = HashMap::new();
+            ours.set_incremental_resize(incremental);
+            let mut std = std::collections::HashMap::new();
+            let mut seed = 42u64;
+            for _ in 0..10_000 {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let key = (seed >> 33) % 500;
+                if seed >> 62 != 0 {
+                    assert_eq!(ours.insert(key, seed), std.insert(key, seed));
+                } else {
+                    assert_eq!(ours.remove(&key), std.remove(&key));
+                }
+                assert_eq!(ours.len(), std.len());
+            }
+            assert!(std.iter().all(|(k, v)| ours.get(k) == Some(v)));
+            assert_eq!(ours.iter().count(), std.len());
+        }
+    }
+
+    #[test]
+    fn incremental_resize() {
+        let mut map = HashMap::new();
+        map.set_incremental_resize(true);
+
+        let mut resizing = 0;
+        let mut removed = Vec::new();
+        for i in 0..2000 {
+            map.insert(i, i);
+            if map.old_buckets.is_some() {
+                resizing += 1;
+            }
+
+            // Every key has to be findable at every step, whichever set of
+            // buckets it's in, and removing has to work from either.
+            if i % 7 == 0 {
+                assert_eq!(map.remove(&(i / 2)), Some(i / 2));
+                removed.push(i / 2);
+            }
+            if i % 97 == 0 {
+                assert!((0..=i).all(|k| map.get(&k).is_some() != removed.contains(&k)));
+            }
+        }
+        assert!(resizing > 0);
+
+        // Lots of removes can't leave anything behind in the old buckets
+        for i in 1000..2000 {
+            map.remove(&i);
+        }
+        assert!(map.old_buckets.is_none());
+        let expected: Vec<_> = (0..1000).filter(|k| !removed.contains(k)).collect();
+        assert_eq!(map.iter().map(|(&k, _)| k).collect::<Vec<_>>(), expected);
+        assert!(expected.iter().all(|k| map.get(k) == Some(k)));
+    }
+
+    #[test]
+    fn capacity() {
+        let mut map = HashMap::with_capacity(1000);
+        let nbuckets = map.buckets.nbuckets();
+        assert!(map.capacity() >= 1000);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.buckets.nbuckets(), nbuckets);
+
+        map.reserve(1000);
+        assert!(map.capacity() >= 2000);
+        let nbuckets = map.buckets.nbuckets();
+        for i in 1000..2000 {
+            map.entry(i).or_insert(i);
+        }
+        assert_eq!(map.buckets.nbuckets(), nbuckets);
+
+        // Shrinking has to keep the order, and close up the removed slots
+        for i in (0..2000).filter(|i| i % 10 != 0) {
+            map.remove(&i);
+        }
+        map.shrink_to(500);
+        assert!(map.capacity() >= 500 && map.capacity() < 1000);
+        map.shrink_to_fit();
+        assert!(map.capacity() >= 200 && map.capacity() < 500);
+        assert_eq!(map.entries.len(), 200);
+        assert!(map.free.is_empty());
+        let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, (0..2000).step_by(10).collect::<Vec<_>>());
+        assert!(keys.iter().all(|k| map.get(k) == Some(k)));
+        map.insert(1, 1);
+        assert_eq!(map.back(), Some((&1, &1)));
+
+        assert_eq!(map.try_reserve(usize::MAX), Err(TryReserveError::CapacityOverflow));
+        assert_eq!(map.len(), 201);
+
+        let map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        assert_eq!(map.buckets.nbuckets(), map.resize_policy.buckets_for(100).unwrap());
+
+        // Shrinking to more than there's room for already never grows anything
+        let mut map: HashMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        let (nbuckets, entries) = (map.buckets.nbuckets(), map.entries.capacity());
+        assert!(map.capacity() > 1200 && entries < 1200);
+        map.shrink_to(1200);
+        assert_eq!(map.buckets.nbuckets(), nbuckets);
+        assert_eq!(map.entries.capacity(), entries);
+        map.retain(|&k, _| k < 100);
+        map.shrink_to(1200);
+        assert_eq!(map.entries.capacity(), entries);
+        map.shrink_to(100);
+        assert!(map.buckets.nbuckets() < nbuckets && map.entries.capacity() == 100);
+        assert_eq!(map.iter().map(|(&k, _)| k).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+
+        let mut map: HashMap<i32, i32> = HashMap::new();
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 0);
+    }
+
+    #[test]
+    fn empty_maps() {
+        fn lookups(mut map: HashMap<String, i32>) {
+            assert_eq!(map.get("a"), None);
+            assert!(!map.contains_key("a"));
+            assert_eq!(map.get_refresh("a"), None);
+            assert_eq!(map.remove("a"), None);
+            assert_eq!(map.raw_entry().from_key("a"), None);
+            assert_eq!(map.iter().next(), None);
+            assert_eq!(map.pop_front(), None);
+            assert!(map.is_empty());
+        }
+
+        lookups(HashMap::new());
+        lookups(HashMap::with_capacity(0));
+        lookups(HashMap::with_seed(0, 0));
+
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), 1);
+        map.remove("a");
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 0);
+        lookups(map);
+
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i.to_string(), i)).collect();
+        map.drain();
+        lookups(map);
+    }
+
+    #[test]
+    fn fallible() {
+        let mut map = HashMap::new();
+        assert_eq!(map.try_insert("a", 1).ok(), Some(&mut 1));
+        let err = map.try_insert("a", 2).unwrap_err();
+        assert_eq!(err.value, 2);
+        assert_eq!(err.entry.get(), &1);
+        assert_eq!(err.to_string(), r#"failed to insert 2, key "a" already exists with value 1"#);
+        assert_eq!(map.get("a"), Some(&1));
+
+        // The error still has the entry, so it can be used anyway
+        let err = map.try_insert("a", 3).unwrap_err();
+        *err.entry.into_mut() += err.value;
+        assert_eq!(map.get("a"), Some(&4));
+
+        // More than could ever be allocated is caught before asking for it
+        let mut map: HashMap<u64, u64> = HashMap::new();
+        assert_eq!(map.try_reserve(usize::MAX / 2), Err(TryReserveError::CapacityOverflow));
+        assert_eq!(map.capacity(), 0);
+
+        // Asking for petabytes isn't an overflow on 64 bit, so this relies on
+        // the allocator turning it down, which any real one will
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert_eq!(map.try_reserve(1 << 50), Err(TryReserveError::AllocError));
+            assert_eq!(map.capacity(), 0);
+        }
+        assert_eq!(map.try_reserve(10), Ok(()));
+        assert!(map.capacity() >= 10);
+    }
+
+    #[test]
+    fn resize_policy() {
+        // insert and entry have to agree on when the map is full
+        let mut a = HashMap::with_seed(1, 2);
+        let mut b = HashMap::with_seed(1, 2);
+        for i in 0..1000 {
+            a.insert(i, i);
+            b.entry(i).or_insert(i);
+            assert_eq!(a.buckets.nbuckets(), b.buckets.nbuckets());
+            assert!(a.len() <= a.capacity());
+        }
+
+        for &incremental in &[false, true] {
+            let mut map = HashMap::new();
+            map.set_incremental_resize(incremental);
+            map.set_resize_policy(
+                ResizePolicy::new()
+                    .table_size(TableSize::Prime)
+                    .max_load(0.9)
+                    .growth(1.5)
+                    .min_buckets(20)
+                    .shrink_below(0.2),
+            );
+
+            map.insert(0, 0);
+            assert_eq!(map.buckets.nbuckets(), 23);
+            for i in 1..1000 {
+                map.insert(i, i);
+                assert!(map.len() <= map.capacity());
+            }
+            let grown = map.buckets.nbuckets();
+            assert!(grown >= 1000);
+
+            // Removing most of them shrinks the table, but leaves the order alone
+            for i in 0..1000 {
+                if i % 20 != 0 {
+                    map.remove(&i);
+                }
+            }
+            map.set_incremental_resize(false);
+            assert!(map.buckets.nbuckets() < grown / 4);
+            let keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+            assert_eq!(keys, (0..1000).step_by(20).collect::<Vec<_>>());
+            assert!(keys.iter().all(|k| map.get(k) == Some(k)));
+        }
+    }
+
+    #[test]
+    fn into_iter_order() {
+        let pairs = vec![("e", 5), ("a", 1), ("d", 4), ("b", 2), ("c", 3)];
+        let map: HashMap<_, _> = pairs.iter().cloned().collect();
+        assert_eq!(map.into_iter().collect::<Vec<_>>(), pairs);
+    }
 }
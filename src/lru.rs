@@ -0,0 +1,212 @@
+use std::hash::{BuildHasher, Hash};
+
+use crate::{Entry, Equivalent, HashMap, Iter, RandomState, RemoveEldest};
+
+// LruCache is a HashMap in access order with a fixed capacity. Once it's
+// full, inserting a new key pushes out whichever entry was used the longest
+// time ago, which is always the one at the front of the map.
+pub struct LruCache<K, V, S = RandomState> {
+    map: HashMap<K, V, S>,
+    capacity: usize,
+}
+
+// Capacity is the remove eldest policy every LruCache installs on its map,
+// so however a new key gets in, the least recently used entry goes once
+// there's one too many.
+struct Capacity(usize);
+
+impl<K, V> RemoveEldest<K, V> for Capacity {
+    fn remove_eldest(&mut self, len: usize, _: &K, _: &V) -> bool {
+        len > self.0
+    }
+}
+
+impl<K, V> LruCache<K, V, RandomState>
+where
+    K: Hash + Eq,
+{
+    pub fn new(capacity: usize) -> Self {
+        LruCache::with_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K, V, S> LruCache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn with_hasher(capacity: usize, hash_builder: S) -> Self {
+        assert!(capacity > 0, "LruCache capacity must be greater than zero");
+        let mut map = HashMap::with_hasher(hash_builder);
+        map.set_access_order(true);
+        map.set_remove_eldest(Capacity(capacity));
+        LruCache { map, capacity }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // insert adds or updates a key, making it the most recently used, and
+    // returns the least recently used entry if it had to be evicted to make
+    // room. Updating a key that's already cached never evicts anything.
+    //
+    // It evicts before inserting, rather than leaving it to the map's
+    // remove eldest policy, so it can hand the evicted entry back.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        let evicted = self.make_room(&key);
+        self.map.insert(key, value);
+        evicted
+    }
+
+    // entry works the same as `HashMap::entry`, so `or_insert_with` can be
+    // used to load a value on a cache miss. Nothing is evicted unless a new
+    // key actually gets inserted, and then the least recently used entry is
+    // dropped, as there's no way to hand it back from `VacantEntry::insert`.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S> {
+        self.map.entry(key)
+    }
+
+    // get looks up a key and marks it as the most recently used.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get_refresh(key).map(|v| &*v)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.get_refresh(key)
+    }
+
+    // peek looks up a key without changing how recently it was used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.peek(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.map.remove(key)
+    }
+
+    // pop_lru removes and returns the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        self.map.pop_front()
+    }
+
+    // resize changes the capacity, evicting the least recently used entries
+    // if the cache is now holding more than it's allowed to.
+    pub fn resize(&mut self, capacity: usize) {
+        assert!(capacity > 0, "LruCache capacity must be greater than zero");
+        self.capacity = capacity;
+        self.map.set_remove_eldest(Capacity(capacity));
+        while self.map.len() > capacity {
+            self.map.pop_front();
+        }
+    }
+
+    // iter goes from the least to the most recently used entry.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    fn make_room(&mut self, key: &K) -> Option<(K, V)> {
+        if self.map.len() < self.capacity || self.map.contains_key(key) {
+            return None;
+        }
+        self.map.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        assert_eq!(cache.insert("a", 1), None);
+        assert_eq!(cache.insert("b", 2), None);
+
+        // Using "a" makes "b" the least recently used
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.insert("c", 3), Some(("b", 2)));
+        assert_eq!(cache.insert("a", 10), None);
+        assert_eq!(cache.len(), 2);
+
+        // peek doesn't count as a use
+        assert_eq!(cache.peek(&"c"), Some(&3));
+        assert_eq!(cache.pop_lru(), Some(("c", 3)));
+        assert_eq!(cache.pop_lru(), Some(("a", 10)));
+        assert_eq!(cache.pop_lru(), None);
+    }
+
+    #[test]
+    fn entry_and_resize() {
+        let mut cache = LruCache::new(3);
+        let mut loads = 0;
+        for key in &[1, 2, 1, 3, 4, 1, 2] {
+            cache.entry(*key).or_insert_with(|| {
+                loads += 1;
+                key * 10
+            });
+        }
+        assert_eq!(loads, 5);
+
+        let keys: Vec<_> = cache.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec![4, 1, 2]);
+
+        cache.resize(1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.peek(&2), Some(&20));
+    }
+
+    #[test]
+    fn entry_only_evicts_on_insert() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+
+        // Looking at a missing key, without inserting it, keeps everything
+        match cache.entry("c") {
+            Entry::Vacant(e) => assert_eq!(e.into_key(), "c"),
+            Entry::Occupied(_) => unreachable!(),
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.peek(&"a"), Some(&1));
+
+        // Inserting it pushes out the least recently used
+        cache.entry("c").or_insert(3);
+        let keys: Vec<_> = cache.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["b", "c"]);
+
+        cache.resize(3);
+        cache.entry("d").or_insert(4);
+        cache.entry("e").or_insert(5);
+        let keys: Vec<_> = cache.iter().map(|(&k, _)| k).collect();
+        assert_eq!(keys, vec!["c", "d", "e"]);
+    }
+}
@@ -0,0 +1,282 @@
+// Methods for looking at and changing the order of the entries directly,
+// rather than just by inserting them. None of them touch the buckets, only
+// the links between the entries, so moving an entry about never rehashes.
+//
+// Moving an entry about is the same in access order, and it stays put until
+// it's next accessed, when it goes to the back as usual.
+
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use crate::{Equivalent, HashMap, MIGRATE_STEP, NIL};
+
+impl<K, V, S> HashMap<K, V, S> {
+    // front is the oldest entry, or the least recently used in access order.
+    pub fn front(&self) -> Option<(&K, &V)> {
+        self.at(self.head.get())
+    }
+
+    // back is the newest entry, or the most recently used in access order.
+    pub fn back(&self) -> Option<(&K, &V)> {
+        self.at(self.tail.get())
+    }
+
+    fn at(&self, index: usize) -> Option<(&K, &V)> {
+        if index == NIL {
+            return None;
+        }
+        let node = self.node(index);
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    // pop_front removes the entry at the front of the map, which is the
+    // oldest one, or the least recently used one in access order.
+    pub fn pop_front(&mut self) -> Option<(K, V)> {
+        self.pop(self.head.get())
+    }
+
+    // pop_back removes the entry at the back of the map, which is the newest
+    // one, or the most recently used one in access order.
+    pub fn pop_back(&mut self) -> Option<(K, V)> {
+        self.pop(self.tail.get())
+    }
+
+    fn pop(&mut self, index: usize) -> Option<(K, V)> {
+        if index == NIL {
+            return None;
+        }
+
+        self.migrate(MIGRATE_STEP);
+        let node = self.remove_index(index);
+        self.shrink_if_sparse();
+        Some((node.key, node.value))
+    }
+
+    // move_to_front moves an entry to the front of the map, returning false
+    // if the key isn't there.
+    pub fn move_to_front<Q>(&mut self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.find(self.hash(key), key) {
+            Some((_, index)) => {
+                if self.head.get() != index {
+                    self.unlink(index);
+                    self.link_front(index);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // move_to_back moves an entry to the back of the map, returning false
+    // if the key isn't there. It's the same as `get_refresh`, without the
+    // value.
+    pub fn move_to_back<Q>(&mut self, key: &Q) -> bool
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        match self.find(self.hash(key), key) {
+            Some((_, index)) => {
+                self.move_back(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // insert_before inserts a key just before `anchor`, or moves it there
+    // if it's already in the map, replacing its value and returning the old
+    // one, the same as `insert`. If `anchor` isn't in the map, nothing is
+    // inserted, and the key and value are handed back.
+    pub fn insert_before<Q>(&mut self, anchor: &Q, key: K, value: V) -> Result<Option<V>, (K, V)>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.insert_next_to(anchor, key, value, true)
+    }
+
+    // insert_after is the same as `insert_before`, but just after `anchor`.
+    pub fn insert_after<Q>(&mut self, anchor: &Q, key: K, value: V) -> Result<Option<V>, (K, V)>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.insert_next_to(anchor, key, value, false)
+    }
+
+    fn insert_next_to<Q>(
+        &mut self,
+        anchor: &Q,
+        key: K,
+        value: V,
+        before: bool,
+    ) -> Result<Option<V>, (K, V)>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        self.make_room();
+
+        let anchor = match self.find(self.hash(anchor), anchor) {
+            Some((_, anchor)) => anchor,
+            None => return Err((key, value)),
+        };
+
+        let hash = self.hash(&key);
+        let (index, old) = match self.find(hash, &key) {
+            Some((_, index)) => (index, Some(mem::replace(&mut self.node_mut(index).value, value))),
+            None => {
+                let index = self.alloc(hash, key, value);
+                // `make_room` already took care of any migrating
+                let probed = self.buckets.insert(hash, index);
+                self.check_chain(probed);
+                (index, None)
+            }
+        };
+
+        // Inserting the anchor before or after itself just replaces its value
+        if index != anchor {
+            self.unlink(index);
+            if before {
+                self.link_before(index, anchor);
+            } else {
+                self.link_after(index, anchor);
+            }
+        }
+        if old.is_none() {
+            self.evict_eldest(index);
+        }
+        Ok(old)
+    }
+
+    // swap_positions swaps where two entries are in the map, leaving
+    // everything else where it is. It returns false, and doesn't change
+    // anything, if either key isn't there.
+    pub fn swap_positions<Q1, Q2>(&mut self, a: &Q1, b: &Q2) -> bool
+    where
+        Q1: Hash + Equivalent<K> + ?Sized,
+        Q2: Hash + Equivalent<K> + ?Sized,
+    {
+        let (a, b) = match (self.find(self.hash(a), a), self.find(self.hash(b), b)) {
+            (Some((_, a)), Some((_, b))) => (a, b),
+            _ => return false,
+        };
+        if a == b {
+            return true;
+        }
+
+        // Next to each other, one of them just hops over the other
+        if self.node(a).next.get() == b {
+            self.unlink(a);
+            self.link_after(a, b);
+        } else if self.node(b).next.get() == a {
+            self.unlink(b);
+            self.link_after(b, a);
+        } else {
+            // Otherwise, each goes after whatever came before the other,
+            // which isn't moving, as they're not next to each other
+            let (a_prev, b_prev) = (self.node(a).prev.get(), self.node(b).prev.get());
+            self.unlink(a);
+            self.unlink(b);
+            match b_prev {
+                NIL => self.link_front(a),
+                prev => self.link_after(a, prev),
+            }
+            match a_prev {
+                NIL => self.link_front(b),
+                prev => self.link_after(b, prev),
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResizePolicy;
+
+    fn keys<V>(map: &HashMap<&'static str, V>) -> Vec<&'static str> {
+        map.iter().map(|(&k, _)| k).collect()
+    }
+
+    #[test]
+    fn reorder() {
+        let mut map: HashMap<_, _> = ["auth", "log", "gzip"].iter().map(|&k| (k, 0)).collect();
+        assert_eq!(map.front(), Some((&"auth", &0)));
+        assert_eq!(map.back(), Some((&"gzip", &0)));
+
+        assert!(map.move_to_front("log"));
+        assert_eq!(keys(&map), ["log", "auth", "gzip"]);
+        assert!(map.move_to_back("log"));
+        assert_eq!(keys(&map), ["auth", "gzip", "log"]);
+        assert!(!map.move_to_front("cors"));
+
+        assert_eq!(map.insert_before("auth", "cors", 1), Ok(None));
+        assert_eq!(map.insert_after("gzip", "cache", 2), Ok(None));
+        assert_eq!(keys(&map), ["cors", "auth", "gzip", "cache", "log"]);
+
+        // An existing key moves, and keeps its slot in the buckets
+        assert_eq!(map.insert_after("log", "cors", 3), Ok(Some(1)));
+        assert_eq!(map.insert_before("auth", "auth", 4), Ok(Some(0)));
+        assert_eq!(map.insert_before("rate", "rate", 5), Err(("rate", 5)));
+        assert_eq!(keys(&map), ["auth", "gzip", "cache", "log", "cors"]);
+        assert_eq!(map.get("cors"), Some(&3));
+        assert_eq!(map.len(), 5);
+
+        // Swapping the ends, neighbours either way round, and one with itself
+        assert!(map.swap_positions("auth", "cors"));
+        assert_eq!(keys(&map), ["cors", "gzip", "cache", "log", "auth"]);
+        assert!(map.swap_positions("gzip", "cache"));
+        assert!(map.swap_positions("auth", "log"));
+        assert!(map.swap_positions("log", "log"));
+        assert!(!map.swap_positions("log", "rate"));
+        assert_eq!(keys(&map), ["cors", "cache", "gzip", "auth", "log"]);
+
+        // The links still have to hold together both ways
+        assert_eq!(map.pop_back(), Some(("log", 0)));
+        assert_eq!(map.pop_front(), Some(("cors", 3)));
+        assert_eq!(map.pop_back(), Some(("auth", 4)));
+        assert_eq!(keys(&map), ["cache", "gzip"]);
+        assert!(map.get("log").is_none() && map.get("cache").is_some());
+        map.insert("log", 0);
+        assert_eq!(keys(&map), ["cache", "gzip", "log"]);
+    }
+
+    #[test]
+    fn insert_next_to_evicts() {
+        let mut map = HashMap::new();
+        map.set_remove_eldest(|len, _: &_, _: &_| len > 3);
+        for k in &["a", "b", "c"] {
+            map.insert(*k, 0);
+        }
+
+        // Going in at the front means it's the eldest, so nothing goes
+        assert_eq!(map.insert_before("a", "z", 0), Ok(None));
+        assert_eq!(keys(&map), ["z", "a", "b", "c"]);
+        map.pop_front();
+
+        // Otherwise it's the same as insert, the eldest goes
+        assert_eq!(map.insert_after("a", "y", 0), Ok(None));
+        assert_eq!(keys(&map), ["y", "b", "c"]);
+    }
+
+    #[test]
+    fn pop_shrinks() {
+        let mut map: HashMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        map.set_resize_policy(ResizePolicy::new().shrink_below(0.2));
+        let grown = map.capacity();
+        while map.len() > 10 {
+            map.pop_front();
+        }
+        assert!(map.capacity() < grown / 4);
+        assert_eq!(map.front(), Some((&990, &990)));
+        assert_eq!(map.pop_back(), Some((999, 999)));
+    }
+}
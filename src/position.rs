@@ -0,0 +1,298 @@
+// Methods for getting at entries by their position in the map, the same as
+// `IndexMap`'s. The order itself is a linked list, so positions come from a
+// separate index, `Positions`, which holds the slot of the entry at each
+// position, and the position of the entry in each slot.
+//
+// The index is built the first time something asks for a position, which
+// is O(n), and after that `get_index`, `get_index_mut`, `get_index_of` and
+// `insert_full` are all O(1). Inserting new keys at the back, or popping
+// them off it, keeps it up to date, and so do the methods here, which cost
+// the same as IndexMap's: O(1) for `swap_remove_index`, O(n) for
+// `shift_remove_index` and `move_index`.
+//
+// Anything else which changes the order somewhere other than the back,
+// like `remove`, the methods in order.rs, or a lookup moving an entry in
+// access order, throws the index away, and the next method here builds it
+// again. So going back and forth between those and positions costs O(n)
+// each time, the same as IndexMap's `shift_remove` would.
+
+use std::hash::{BuildHasher, Hash};
+
+use crate::{Entry, Equivalent, HashMap, MIGRATE_STEP, NIL};
+
+#[derive(Clone, Default)]
+pub(crate) struct Positions {
+    // The slot of the entry at each position.
+    order: Vec<usize>,
+    // The position of the entry in each slot. Free slots have stale ones.
+    at: Vec<usize>,
+}
+
+impl Positions {
+    pub(crate) fn push(&mut self, index: usize) {
+        if self.at.len() <= index {
+            self.at.resize(index + 1, NIL);
+        }
+        self.at[index] = self.order.len();
+        self.order.push(index);
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.order.pop();
+    }
+
+    // renumber fixes up `at` for the positions in `range`, after the slots
+    // in `order` have been moved about.
+    fn renumber(&mut self, range: std::ops::Range<usize>) {
+        for i in range {
+            self.at[self.order[i]] = i;
+        }
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    pub fn get_index(&self, i: usize) -> Option<(&K, &V)> {
+        let index = self.positions(|positions| positions.order.get(i).copied())?;
+        let node = self.node(index);
+        Some((&node.key, &node.value))
+    }
+
+    pub fn get_index_mut(&mut self, i: usize) -> Option<(&K, &mut V)> {
+        let index = self.positions(|positions| positions.order.get(i).copied())?;
+        let node = self.node_mut(index);
+        Some((&node.key, &mut node.value))
+    }
+
+    // positions runs `f` on the index, building it first if it isn't there.
+    fn positions<R>(&self, f: impl FnOnce(&Positions) -> R) -> R {
+        let mut positions = self.positions.borrow_mut();
+        let positions = positions.get_or_insert_with(|| {
+            let mut positions = Positions {
+                order: Vec::with_capacity(self.items),
+                at: vec![NIL; self.entries.len()],
+            };
+            let mut index = self.head.get();
+            while index != NIL {
+                positions.push(index);
+                index = self.node(index).next.get();
+            }
+            positions
+        });
+        f(positions)
+    }
+
+    // take_positions hands over the index, built, so one of the methods
+    // here can keep it up to date itself, rather than the linked list
+    // changes it makes throwing it away. It goes back with `put_positions`.
+    fn take_positions(&mut self) -> Positions {
+        self.positions(|_| ());
+        self.positions.take().expect("positions were just built")
+    }
+
+    fn put_positions(&mut self, positions: Positions) {
+        *self.positions.get_mut() = Some(positions);
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn get_index_of<Q>(&self, key: &Q) -> Option<usize>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let (_, index) = self.find(self.hash(key), key)?;
+        Some(self.positions(|positions| positions.at[index]))
+    }
+
+    // insert_full is `insert`, but also returns the position the key ended
+    // up at. A new key always goes at the back.
+    pub fn insert_full(&mut self, key: K, value: V) -> (usize, Option<V>) {
+        match self.entry(key) {
+            Entry::Occupied(mut e) => {
+                let old = e.insert(value);
+                let index = e.index;
+                (self.positions(|positions| positions.at[index]), Some(old))
+            }
+            Entry::Vacant(e) => {
+                e.insert(value);
+                (self.items - 1, None)
+            }
+        }
+    }
+
+    // swap_remove_index removes the entry at position `i`, and moves the
+    // last entry into its place, so nothing else changes position.
+    pub fn swap_remove_index(&mut self, i: usize) -> Option<(K, V)> {
+        let index = self.positions(|positions| positions.order.get(i).copied())?;
+        let mut positions = self.take_positions();
+
+        let last = self.tail.get();
+        if last != index {
+            self.unlink(last);
+            self.link_before(last, index);
+        }
+        let removed = self.remove_at(index);
+
+        positions.order.swap_remove(i);
+        positions.renumber(i..(i + 1).min(positions.order.len()));
+        self.put_positions(positions);
+        Some(removed)
+    }
+
+    // shift_remove_index removes the entry at position `i`, and everything
+    // after it moves up one.
+    pub fn shift_remove_index(&mut self, i: usize) -> Option<(K, V)> {
+        let index = self.positions(|positions| positions.order.get(i).copied())?;
+        let mut positions = self.take_positions();
+
+        let removed = self.remove_at(index);
+
+        positions.order.remove(i);
+        positions.renumber(i..positions.order.len());
+        self.put_positions(positions);
+        Some(removed)
+    }
+
+    fn remove_at(&mut self, index: usize) -> (K, V) {
+        self.migrate(MIGRATE_STEP);
+        let node = self.remove_index(index);
+        self.shrink_if_sparse();
+        (node.key, node.value)
+    }
+
+    // move_index moves the entry at position `from` to position `to`, and
+    // shifts the ones in between along to make room. It panics if either
+    // position is out of bounds, the same as IndexMap's.
+    pub fn move_index(&mut self, from: usize, to: usize) {
+        let len = self.items;
+        if from >= len || to >= len {
+            panic!("move_index out of bounds: from {} to {} with length {}", from, to, len);
+        }
+        if from == to {
+            return;
+        }
+        let mut positions = self.take_positions();
+
+        let (index, target) = (positions.order[from], positions.order[to]);
+        self.unlink(index);
+        if from < to {
+            self.link_after(index, target);
+        } else {
+            self.link_before(index, target);
+        }
+
+        positions.order.remove(from);
+        positions.order.insert(to, index);
+        positions.renumber(from.min(to)..from.max(to) + 1);
+        self.put_positions(positions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(map: &HashMap<i32, i32>) -> Vec<i32> {
+        map.iter().map(|(&k, _)| k).collect()
+    }
+
+    // check makes sure every position agrees with the linked list.
+    fn check(map: &HashMap<i32, i32>) {
+        for (i, (k, v)) in map.iter().enumerate() {
+            assert_eq!(map.get_index(i), Some((k, v)));
+            assert_eq!(map.get_index_of(k), Some(i));
+        }
+        assert_eq!(map.get_index(map.len()), None);
+    }
+
+    #[test]
+    fn positions() {
+        let mut map: HashMap<_, _> = (0..10).map(|i| (i, i * 10)).collect();
+        for i in 0..10 {
+            assert_eq!(map.get_index(i as usize), Some((&i, &(i * 10))));
+            assert_eq!(map.get_index_of(&i), Some(i as usize));
+        }
+        assert_eq!(map.get_index(10), None);
+        assert_eq!(map.get_index_of(&10), None);
+        *map.get_index_mut(3).unwrap().1 += 1;
+        assert_eq!(map.get(&3), Some(&31));
+
+        assert_eq!(map.insert_full(10, 100), (10, None));
+        assert_eq!(map.insert_full(4, 41), (4, Some(40)));
+
+        // The map is 0 to 10 in order here
+        assert_eq!(map.swap_remove_index(1), Some((1, 10)));
+        assert_eq!(map.shift_remove_index(3), Some((3, 31)));
+        assert_eq!(map.swap_remove_index(8), Some((9, 90)));
+        assert_eq!(map.shift_remove_index(8), None);
+        assert_eq!(keys(&map), [0, 10, 2, 4, 5, 6, 7, 8]);
+        check(&map);
+
+        map.move_index(0, 7);
+        map.move_index(5, 1);
+        map.move_index(3, 3);
+        assert_eq!(keys(&map), [10, 7, 2, 4, 5, 6, 8, 0]);
+        assert_eq!(map.get_index_of(&7), Some(1));
+        assert_eq!(map.pop_back(), Some((0, 0)));
+        assert_eq!(map.get_index(6), Some((&8, &80)));
+        check(&map);
+
+        let mut map = HashMap::with_access_order(true);
+        map.extend((0..5).map(|i| (i, i)));
+        assert_eq!(map.insert_full(1, 1), (4, Some(1)));
+    }
+
+    #[test]
+    fn index_kept_up_to_date() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        check(&map);
+
+        // Pushing and popping at the back, and the methods here, keep it
+        for i in 100..200 {
+            map.insert(i, i);
+        }
+        map.pop_back();
+        map.swap_remove_index(10);
+        map.shift_remove_index(20);
+        map.move_index(5, 150);
+        map.move_index(120, 0);
+        assert!(map.positions.borrow().is_some());
+        check(&map);
+
+        // Changing the middle throws it away, and it comes back right
+        map.remove(&50);
+        assert!(map.positions.borrow().is_none());
+        check(&map);
+        map.move_to_front(&150);
+        map.swap_positions(&3, &170);
+        map.insert_before(&60, 1000, 0).unwrap();
+        map.retain(|k, _| k % 7 != 0);
+        check(&map);
+
+        // Free slots get re-used, and shrinking moves everything
+        for i in 0..50 {
+            map.remove(&i);
+        }
+        check(&map);
+        map.extend((2000..2040).map(|i| (i, i)));
+        check(&map);
+        map.shrink_to_fit();
+        check(&map);
+        let clone = map.clone();
+        check(&clone);
+        map.drain();
+        check(&map);
+        map.insert(1, 1);
+        check(&map);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn move_index_out_of_bounds() {
+        let mut map = HashMap::from([(1, 1)]);
+        map.move_index(0, 1);
+    }
+}
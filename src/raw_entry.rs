@@ -0,0 +1,363 @@
+// The raw entry API lets you look entries up with a hash you've already
+// worked out, and a closure to pick out the right key, rather than a key
+// which has to be hashed on every lookup. It's the same idea as hashbrown's
+// raw entries, and is handy for looking the same key up in several maps
+// which share a hasher, or for keys which are expensive to build.
+//
+// Hashes come from `hash_key`, and remember the salt the map was using when
+// they were worked out. The salt changes whenever the map reseeds itself
+// (see `set_collision_threshold`), so a hash can go stale under any insert.
+// A stale one is never used to probe the buckets: the key is hashed again
+// instead, or for `from_hash`, which has no key, every entry is checked.
+// Maps sharing a hash builder, which haven't reseeded, share hashes too.
+
+use std::hash::{BuildHasher, Hash};
+
+use crate::{Bucket, Equivalent, HashMap, OccupiedEntry, RandomState, NIL};
+
+// KeyHash is a key's hash, along with the salt it was hashed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyHash {
+    hash: u64,
+    salt: u64,
+}
+
+pub struct RawEntryBuilder<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    map: &'a HashMap<K, V, S>,
+}
+
+pub struct RawEntryBuilderMut<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    map: &'a mut HashMap<K, V, S>,
+}
+
+// RawEntryMut is what the lookups on `raw_entry_mut` give back. A key which
+// is there is just a normal `OccupiedEntry`.
+pub enum RawEntryMut<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(RawVacantEntryMut<'a, K, V, S>),
+}
+
+pub struct RawVacantEntryMut<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    map: &'a mut HashMap<K, V, S>,
+}
+
+impl<K, V, S> HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    // hash_key works out the hash the map uses for a key, for passing to
+    // the raw entry API.
+    pub fn hash_key<Q>(&self, key: &Q) -> KeyHash
+    where
+        Q: Hash + ?Sized,
+    {
+        KeyHash {
+            hash: self.hash(key),
+            salt: self.salt,
+        }
+    }
+
+    // current gives back the hash to probe the buckets with, as long as
+    // the map hasn't reseeded since it was worked out.
+    fn current(&self, hash: KeyHash) -> Option<u64> {
+        if hash.salt == self.salt {
+            Some(hash.hash)
+        } else {
+            None
+        }
+    }
+
+    // refresh works out the hash of `key` again if `hash` is stale.
+    fn refresh<Q>(&self, hash: KeyHash, key: &Q) -> KeyHash
+    where
+        Q: Hash + ?Sized,
+    {
+        match self.current(hash) {
+            Some(_) => hash,
+            None => self.hash_key(key),
+        }
+    }
+
+    // raw_entry looks entries up without changing anything, even in access
+    // order, the same as `peek`.
+    pub fn raw_entry(&self) -> RawEntryBuilder<'_, K, V, S> {
+        RawEntryBuilder { map: self }
+    }
+
+    // raw_entry_mut looks entries up so they can be changed or inserted.
+    // It makes room for an insert up front, the same as `entry` does.
+    pub fn raw_entry_mut(&mut self) -> RawEntryBuilderMut<'_, K, V, S> {
+        self.make_room();
+        RawEntryBuilderMut { map: self }
+    }
+
+    // find_raw looks for the entry with the given hash that `is_match`
+    // accepts. There's no key to hash again if the hash is stale, so that
+    // has to go through every entry, rather than the buckets.
+    fn find_raw<F>(&self, hash: KeyHash, mut is_match: F) -> Option<(Bucket, usize)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let entries = &self.entries;
+        if let Some(hash) = self.current(hash) {
+            return self.find_by(hash, |index| match entries[index] {
+                Some(ref node) => node.hash == hash && is_match(&node.key),
+                None => false,
+            });
+        }
+
+        let mut index = self.head.get();
+        while index != NIL {
+            let node = self.node(index);
+            if is_match(&node.key) {
+                return self.find_by(node.hash, |i| i == index);
+            }
+            index = node.next.get();
+        }
+        None
+    }
+}
+
+impl<'a, K, V, S> RawEntryBuilder<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn from_key<Q>(self, key: &Q) -> Option<(&'a K, &'a V)>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = self.map.hash_key(key);
+        self.from_key_hashed_nocheck(hash, key)
+    }
+
+    // from_key_hashed_nocheck trusts that `hash` is the hash of `key`,
+    // unless it's stale, when `key` is hashed again.
+    pub fn from_key_hashed_nocheck<Q>(self, hash: KeyHash, key: &Q) -> Option<(&'a K, &'a V)>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = self.map.refresh(hash, key);
+        self.from_hash(hash, |k| key.equivalent(k))
+    }
+
+    pub fn from_hash<F>(self, hash: KeyHash, is_match: F) -> Option<(&'a K, &'a V)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let (_, index) = self.map.find_raw(hash, is_match)?;
+        let node = self.map.node(index);
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K, V, S> RawEntryBuilderMut<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn from_key<Q>(self, key: &Q) -> RawEntryMut<'a, K, V, S>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = self.map.hash_key(key);
+        self.from_key_hashed_nocheck(hash, key)
+    }
+
+    // from_key_hashed_nocheck trusts that `hash` is the hash of `key`,
+    // unless it's stale, when `key` is hashed again.
+    pub fn from_key_hashed_nocheck<Q>(self, hash: KeyHash, key: &Q) -> RawEntryMut<'a, K, V, S>
+    where
+        Q: Hash + Equivalent<K> + ?Sized,
+    {
+        let hash = self.map.refresh(hash, key);
+        self.from_hash(hash, |k| key.equivalent(k))
+    }
+
+    pub fn from_hash<F>(self, hash: KeyHash, is_match: F) -> RawEntryMut<'a, K, V, S>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        match self.map.find_raw(hash, is_match) {
+            Some((bucket, index)) => {
+                self.map.touch(index);
+                RawEntryMut::Occupied(OccupiedEntry {
+                    key: None,
+                    map: self.map,
+                    bucket,
+                    index,
+                })
+            }
+            None => RawEntryMut::Vacant(RawVacantEntryMut { map: self.map }),
+        }
+    }
+}
+
+impl<'a, K, V, S> RawEntryMut<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    // or_insert returns the entry's key and value, inserting the given ones
+    // if it isn't there.
+    pub fn or_insert(self, key: K, value: V) -> (&'a mut K, &'a mut V) {
+        self.or_insert_with(|| (key, value))
+    }
+
+    pub fn or_insert_with<F>(self, maker: F) -> (&'a mut K, &'a mut V)
+    where
+        F: FnOnce() -> (K, V),
+    {
+        match self {
+            RawEntryMut::Occupied(e) => e.into_key_value(),
+            RawEntryMut::Vacant(e) => {
+                let (key, value) = maker();
+                e.insert(key, value)
+            }
+        }
+    }
+
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut K, &mut V),
+    {
+        match self {
+            RawEntryMut::Occupied(e) => {
+                let node = e.map.node_mut(e.index);
+                f(&mut node.key, &mut node.value);
+                RawEntryMut::Occupied(e)
+            }
+            RawEntryMut::Vacant(e) => RawEntryMut::Vacant(e),
+        }
+    }
+}
+
+impl<'a, K, V, S> RawVacantEntryMut<'a, K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    // insert hashes the key again, so it can't end up in the wrong place
+    // whatever hash it was looked up with.
+    pub fn insert(self, key: K, value: V) -> (&'a mut K, &'a mut V) {
+        let hash = self.map.hash_key(&key);
+        self.insert_hashed_nocheck(hash, key, value)
+    }
+
+    // insert_hashed_nocheck trusts that `hash` is the hash of `key`, unless
+    // it's stale, when `key` is hashed again.
+    pub fn insert_hashed_nocheck(self, hash: KeyHash, key: K, value: V) -> (&'a mut K, &'a mut V) {
+        let hash = self.map.refresh(hash, &key).hash;
+        let index = self.map.alloc(hash, key, value);
+        // `raw_entry_mut` already made room
+        let probed = self.map.buckets.insert(hash, index);
+        self.map.check_chain(probed);
+        self.map.evict_eldest(index);
+
+        let node = self.map.node_mut(index);
+        (&mut node.key, &mut node.value)
+    }
+}
+
+impl<'a, K: 'a, V: 'a, S: 'a> OccupiedEntry<'a, K, V, S> {
+    // into_key_value hands out the key along with the value. Changing the
+    // key so it hashes or compares differently will lose track of it.
+    pub fn into_key_value(self) -> (&'a mut K, &'a mut V) {
+        let node = self.map.node_mut(self.index);
+        (&mut node.key, &mut node.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_entry() {
+        let mut map = HashMap::new();
+        for i in 0..100 {
+            map.insert(i.to_string(), i);
+        }
+
+        let hash = map.hash_key("42");
+        assert_eq!(hash, map.hash_key(&"42".to_string()));
+        assert_eq!(map.raw_entry().from_hash(hash, |k| k == "42"), Some((&"42".to_string(), &42)));
+        assert_eq!(map.raw_entry().from_key_hashed_nocheck(hash, "42").map(|(_, &v)| v), Some(42));
+        assert_eq!(map.raw_entry().from_key("100"), None);
+
+        // The right hash with the wrong key, and the other way round
+        assert_eq!(map.raw_entry().from_hash(hash, |k| k == "43"), None);
+        let wrong = KeyHash {
+            hash: hash.hash ^ 1,
+            ..hash
+        };
+        assert_eq!(map.raw_entry().from_hash(wrong, |k| k == "42"), None);
+
+        match map.raw_entry_mut().from_key_hashed_nocheck(hash, "42") {
+            RawEntryMut::Occupied(e) => assert_eq!(e.remove_entry(), ("42".to_string(), 42)),
+            RawEntryMut::Vacant(_) => unreachable!(),
+        }
+        match map.raw_entry_mut().from_hash(hash, |k| k == "42") {
+            RawEntryMut::Vacant(e) => {
+                let (k, v) = e.insert_hashed_nocheck(hash, "42".to_string(), 420);
+                assert_eq!((k.as_str(), *v), ("42", 420));
+            }
+            RawEntryMut::Occupied(_) => unreachable!(),
+        }
+        assert_eq!(map.get("42"), Some(&420));
+        assert_eq!(map.back(), Some((&"42".to_string(), &420)));
+
+        let (_, v) = map
+            .raw_entry_mut()
+            .from_key("7")
+            .and_modify(|_, v| *v *= 10)
+            .or_insert("7".to_string(), 0);
+        assert_eq!(*v, 70);
+        let (_, v) = map.raw_entry_mut().from_key("seven").or_insert("seven".to_string(), 7);
+        assert_eq!(*v, 7);
+        assert_eq!(map.len(), 101);
+    }
+
+    #[test]
+    fn stale_hashes() {
+        let mut map = HashMap::with_seed(1, 2);
+        let mut other = HashMap::with_seed(1, 2);
+        for i in 0..10 {
+            map.insert(i, i);
+            other.insert(i, -i);
+        }
+
+        // Hashed once, for both maps
+        let hash = map.hash_key(&7);
+        assert_eq!(map.raw_entry().from_key_hashed_nocheck(hash, &7), Some((&7, &7)));
+        assert_eq!(other.raw_entry().from_key_hashed_nocheck(hash, &7), Some((&7, &-7)));
+
+        // Reseeding a few times leaves the hash stale
+        map.set_collision_threshold(0);
+        for i in 10..1000 {
+            map.insert(i, i);
+        }
+        assert!(map.collision_stats().reseeds > 1);
+        assert_ne!(map.hash_key(&7), hash);
+
+        assert_eq!(map.raw_entry().from_key_hashed_nocheck(hash, &7), Some((&7, &7)));
+        assert_eq!(map.raw_entry().from_hash(hash, |&k| k == 7), Some((&7, &7)));
+        assert_eq!(other.raw_entry().from_hash(hash, |&k| k == 7), Some((&7, &-7)));
+        match map.raw_entry_mut().from_hash(hash, |&k| k == 7) {
+            RawEntryMut::Occupied(e) => assert_eq!(e.remove_entry(), (7, 7)),
+            RawEntryMut::Vacant(_) => unreachable!(),
+        }
+        match map.raw_entry_mut().from_key_hashed_nocheck(hash, &7) {
+            RawEntryMut::Vacant(e) => e.insert_hashed_nocheck(hash, 7, 70),
+            RawEntryMut::Occupied(_) => unreachable!(),
+        };
+        match map.raw_entry_mut().from_key_hashed_nocheck(hash, &7) {
+            RawEntryMut::Occupied(e) => assert_eq!(e.get(), &70),
+            RawEntryMut::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(map.get(&7), Some(&70));
+        assert_eq!(map.iter().filter(|(&k, _)| k == 7).count(), 1);
+        assert_eq!(map.len(), 1000);
+    }
+}
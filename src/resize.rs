@@ -0,0 +1,224 @@
+// ResizePolicy decides how big the table of buckets is, and when it grows or
+// shrinks. Every path which can add an entry (`insert`, `entry`, `reserve`,
+// `FromIterator`) asks the same policy, so they all agree on when the map is
+// full.
+//
+// How full to let the table get is a trade off. A bucket per item would use
+// loads of memory, whereas if the buckets were nearly full, it would take
+// ages to probe along them looking for a free one.
+
+// The smallest table we'll create by default. It can't actually hold
+// anything, so the first insert always grows it.
+const INITIAL_NBUCKETS: usize = 1;
+
+// TableSize picks which sizes of table the map uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableSize {
+    // Powers of two, which is the default, and what triangular probing
+    // needs to be sure of visiting every group. A hash picks its bucket by
+    // its low bits, so the hasher has to make those good and random.
+    PowerOfTwo,
+    // Powers of two again, but the bucket comes from multiplying the hash
+    // by the golden ratio and taking the top bits (Fibonacci hashing). It's
+    // nearly as cheap as a mask, and copes with hashers which don't mix
+    // their output, like the identity hash of an aligned pointer.
+    Fibonacci,
+    // Prime numbers, which spread the entries out better when the hasher
+    // leaves patterns in the low bits, at the cost of a division per lookup.
+    Prime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResizePolicy {
+    max_load: f64,
+    growth: f64,
+    min_buckets: usize,
+    pub(crate) table_size: TableSize,
+    shrink_load: Option<f64>,
+}
+
+impl ResizePolicy {
+    // new gives the default policy: power of two tables, which are allowed
+    // to get 3/4 full before doubling, and never shrink by themselves.
+    pub fn new() -> ResizePolicy {
+        ResizePolicy {
+            max_load: 0.75,
+            growth: 2.0,
+            min_buckets: INITIAL_NBUCKETS,
+            table_size: TableSize::PowerOfTwo,
+            shrink_load: None,
+        }
+    }
+
+    // max_load is how full the buckets can get before the table grows,
+    // counting tombstones left behind by removed entries. There's always
+    // at least one empty bucket, whatever this is set to.
+    pub fn max_load(mut self, load: f64) -> ResizePolicy {
+        assert!(load > 0.0 && load <= 1.0, "max load must be in (0, 1]");
+        self.max_load = load;
+        self
+    }
+
+    // growth is how many times bigger the table gets each time it grows.
+    pub fn growth(mut self, factor: f64) -> ResizePolicy {
+        assert!(factor > 1.0, "growth factor must be more than 1");
+        self.growth = factor;
+        self
+    }
+
+    // min_buckets stops the table ever being made smaller than this, once
+    // there's anything in it.
+    pub fn min_buckets(mut self, nbuckets: usize) -> ResizePolicy {
+        self.min_buckets = nbuckets.max(1);
+        self
+    }
+
+    pub fn table_size(mut self, table_size: TableSize) -> ResizePolicy {
+        self.table_size = table_size;
+        self
+    }
+
+    // shrink_below makes `remove`, `pop_front` and the other removals shrink
+    // the table once less than `load` of it is in use. It shrinks to a size
+    // with room to grow by a whole growth step before it's full again, so a
+    // map hovering around the threshold doesn't keep flipping between sizes.
+    pub fn shrink_below(mut self, load: f64) -> ResizePolicy {
+        assert!((0.0..1.0).contains(&load), "shrink load must be in [0, 1)");
+        self.shrink_load = Some(load);
+        self
+    }
+
+    // max_items is how many buckets of a table this size can be in use,
+    // tombstones included, before it has to grow.
+    pub(crate) fn max_items(&self, nbuckets: usize) -> usize {
+        let max = (nbuckets as f64 * self.max_load) as usize;
+        max.min(nbuckets.saturating_sub(1))
+    }
+
+    // round gives the smallest table size we allow that's at least `n`.
+    fn round(&self, n: usize) -> Option<usize> {
+        let n = n.max(self.min_buckets);
+        if n > isize::MAX as usize {
+            return None;
+        }
+        match self.table_size {
+            TableSize::PowerOfTwo | TableSize::Fibonacci => n.checked_next_power_of_two(),
+            TableSize::Prime => next_prime(n),
+        }
+    }
+
+    // buckets_for works out how many buckets we need to hold `capacity`
+    // entries without growing.
+    pub(crate) fn buckets_for(&self, capacity: usize) -> Option<usize> {
+        if capacity == 0 {
+            return Some(0);
+        }
+
+        let n = (capacity as f64 / self.max_load).ceil() as usize;
+        let mut n = self.round(n.max(capacity.checked_add(1)?))?;
+        while self.max_items(n) < capacity {
+            n = self.round(n + 1)?;
+        }
+        Some(n)
+    }
+
+    // grow gives the size to grow a table of `nbuckets` to, which has to be
+    // big enough for at least `needed` entries.
+    pub(crate) fn grow(&self, nbuckets: usize, needed: usize) -> Option<usize> {
+        let grown = (nbuckets as f64 * self.growth).ceil() as usize;
+        let grown = self.round(grown.max(nbuckets.checked_add(1)?))?;
+        Some(grown.max(self.buckets_for(needed)?))
+    }
+
+    // shrink gives the size to shrink a table of `nbuckets` down to, if the
+    // policy says `items` is few enough to bother.
+    pub(crate) fn shrink(&self, nbuckets: usize, items: usize) -> Option<usize> {
+        let load = self.shrink_load?;
+        if items as f64 >= nbuckets as f64 * load {
+            return None;
+        }
+
+        let headroom = (items as f64 * self.growth).ceil() as usize;
+        let target = self.buckets_for(headroom)?;
+        if target < nbuckets {
+            Some(target)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ResizePolicy {
+    fn default() -> ResizePolicy {
+        ResizePolicy::new()
+    }
+}
+
+// next_prime finds the smallest prime which is at least `n`. Trial division
+// is plenty fast enough, as we only need one every time the table resizes.
+fn next_prime(n: usize) -> Option<usize> {
+    let mut n = n.max(2);
+    while !is_prime(n) {
+        n = n.checked_add(1)?;
+    }
+    Some(n)
+}
+
+fn is_prime(n: usize) -> bool {
+    if n < 4 {
+        return n >= 2;
+    }
+    if n.is_multiple_of(2) {
+        return false;
+    }
+
+    let mut d = 3;
+    while d <= n / d {
+        if n.is_multiple_of(d) {
+            return false;
+        }
+        d += 2;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        let policy = ResizePolicy::new();
+        assert_eq!(policy.buckets_for(0), Some(0));
+        assert_eq!(policy.buckets_for(1), Some(2));
+        assert_eq!(policy.buckets_for(3), Some(4));
+        assert_eq!(policy.buckets_for(4), Some(8));
+        assert_eq!(policy.buckets_for(usize::MAX), None);
+        assert_eq!(policy.grow(8, 7), Some(16));
+        assert_eq!(policy.grow(8, 100), Some(256));
+
+        let policy = ResizePolicy::new().table_size(TableSize::Prime).growth(1.5);
+        assert_eq!(policy.grow(11, 1), Some(17));
+        for capacity in 1..500 {
+            let n = policy.buckets_for(capacity).unwrap();
+            assert!(is_prime(n));
+            assert!(policy.max_items(n) >= capacity);
+            assert!(policy.max_items(n - 1) < capacity || !is_prime(n - 1));
+        }
+    }
+
+    #[test]
+    fn shrink() {
+        let policy = ResizePolicy::new();
+        assert_eq!(policy.shrink(1024, 1), None);
+
+        // Shrinking leaves the load well clear of both thresholds
+        let policy = policy.shrink_below(0.125);
+        assert_eq!(policy.shrink(1024, 128), None);
+        let target = policy.shrink(1024, 100).unwrap();
+        assert_eq!(target, 512);
+        assert!(policy.max_items(target) >= 200);
+        assert_eq!(policy.shrink(512, 64), None);
+        assert_eq!(policy.shrink(1024, 0), Some(0));
+    }
+}
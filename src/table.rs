@@ -0,0 +1,385 @@
+// RawTable is the hash table part of the map. It's one flat array of
+// buckets, each of which holds the index of an entry, rather than a vector
+// of entries per bucket, so there's a single allocation for the whole table
+// and a lookup never has to chase a pointer into a separate bucket vector.
+//
+// Alongside the buckets is an array of control bytes, one per bucket, the
+// same as Google's SwissTable and the hashbrown crate. A control byte says
+// whether its bucket is EMPTY, DELETED, or full, and when it's full it
+// holds the top 7 bits of the entry's hash. A lookup loads a whole group of
+// control bytes at a time (see `group`) and only looks at the entries whose
+// 7 bits match, so most buckets are ruled out without touching the entries.
+//
+// The table doesn't know anything about keys, the map hands it hashes and
+// a closure which checks whether the entry at an index is the one it wants.
+
+use std::mem;
+
+use crate::group::{Group, DELETED, EMPTY, WIDTH};
+use crate::resize::TableSize;
+use crate::TryReserveError;
+
+// 2^64 divided by the golden ratio, which is what Fibonacci hashing
+// multiplies by. Every bit of the hash ends up affecting the top bits.
+const FIBONACCI: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RawTable {
+    // One byte per bucket, followed by copies of the first buckets' bytes,
+    // so a group loaded from near the end wraps around to the start.
+    ctrl: Vec<u8>,
+    indices: Vec<usize>,
+
+    // Number of DELETED control bytes.
+    tombstones: usize,
+
+    // How a hash is turned into a bucket, which depends on the size.
+    table_size: TableSize,
+}
+
+// The top 7 bits of a hash, which is what goes in a full control byte.
+fn h2(hash: u64) -> u8 {
+    (hash >> 57) as u8
+}
+
+// In Fibonacci mode, the bucket comes from the top bits of the mixed hash,
+// so the control byte is taken from the middle of it instead, well clear
+// of them unless the table has tens of millions of buckets.
+fn fibonacci_h2(mixed: u64) -> u8 {
+    h2(mixed << 25)
+}
+
+// ProbeSeq walks the table a group at a time, jumping one group further
+// each time (triangular probing). When the number of buckets is a power of
+// two, this visits every group once before coming back round. Any other
+// size, say a prime, could skip some altogether, so those just step along
+// one group at a time instead.
+struct ProbeSeq {
+    pos: usize,
+    stride: usize,
+    triangular: bool,
+}
+
+impl ProbeSeq {
+    fn move_next(&mut self, nbuckets: usize) {
+        if self.triangular || self.stride == 0 {
+            self.stride += WIDTH;
+        }
+        self.pos = wrap(self.pos + self.stride, nbuckets);
+    }
+}
+
+// wrap brings a position which has run off the end of the table back round
+// to the start. Most positions don't need it, so we skip the division.
+fn wrap(at: usize, nbuckets: usize) -> usize {
+    if at < nbuckets {
+        at
+    } else {
+        at % nbuckets
+    }
+}
+
+impl RawTable {
+    pub(crate) fn new() -> Self {
+        RawTable {
+            ctrl: Vec::new(),
+            indices: Vec::new(),
+            tombstones: 0,
+            table_size: TableSize::PowerOfTwo,
+        }
+    }
+
+    // with_buckets creates an empty table. Unless `table_size` is Prime,
+    // `nbuckets` has to be a power of two.
+    pub(crate) fn with_buckets(nbuckets: usize, table_size: TableSize) -> Self {
+        debug_assert!(table_size == TableSize::Prime || nbuckets.is_power_of_two() || nbuckets == 0);
+        if nbuckets == 0 {
+            return RawTable::new();
+        }
+        RawTable {
+            ctrl: vec![EMPTY; nbuckets + WIDTH],
+            indices: vec![0; nbuckets],
+            tombstones: 0,
+            table_size,
+        }
+    }
+
+    // try_with_buckets is the same as `with_buckets`, but returns an error
+    // rather than aborting if the memory can't be allocated.
+    pub(crate) fn try_with_buckets(
+        nbuckets: usize,
+        table_size: TableSize,
+    ) -> Result<Self, TryReserveError> {
+        if nbuckets == 0 {
+            return Ok(RawTable::new());
+        }
+        if nbuckets > isize::MAX as usize / mem::size_of::<usize>() {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+
+        let mut ctrl = Vec::new();
+        let mut indices = Vec::new();
+        ctrl.try_reserve_exact(nbuckets + WIDTH)
+            .and_then(|_| indices.try_reserve_exact(nbuckets))
+            .map_err(|_| TryReserveError::AllocError)?;
+        ctrl.resize(nbuckets + WIDTH, EMPTY);
+        indices.resize(nbuckets, 0);
+
+        Ok(RawTable {
+            ctrl,
+            indices,
+            tombstones: 0,
+            table_size,
+        })
+    }
+
+    // clear empties every bucket, but keeps hold of the memory.
+    pub(crate) fn clear(&mut self) {
+        for ctrl in &mut self.ctrl {
+            *ctrl = EMPTY;
+        }
+        self.tombstones = 0;
+    }
+
+    // nbuckets is how many buckets the table has, not how many are in use.
+    pub(crate) fn nbuckets(&self) -> usize {
+        self.indices.len()
+    }
+
+    // tombstones is how many buckets are taken up by removed entries.
+    pub(crate) fn tombstones(&self) -> usize {
+        self.tombstones
+    }
+
+    // probe_seq starts probing from a hash's home bucket. Only prime sized
+    // tables need a division to find it, with a power of two we can just
+    // mask off the low bits, or with Fibonacci hashing take the top bits,
+    // which every bit of the hash has had a hand in.
+    fn probe_seq(&self, hash: u64) -> ProbeSeq {
+        let nbuckets = self.nbuckets();
+        let pos = match self.table_size {
+            TableSize::PowerOfTwo => hash as usize & (nbuckets - 1),
+            TableSize::Fibonacci => {
+                let bits = nbuckets.trailing_zeros();
+                hash.wrapping_mul(FIBONACCI).checked_shr(64 - bits).unwrap_or(0) as usize
+            }
+            TableSize::Prime => (hash % nbuckets as u64) as usize,
+        };
+        ProbeSeq {
+            pos,
+            stride: 0,
+            triangular: self.table_size != TableSize::Prime,
+        }
+    }
+
+    // tag is the control byte for a full bucket holding this hash.
+    fn tag(&self, hash: u64) -> u8 {
+        match self.table_size {
+            TableSize::Fibonacci => fibonacci_h2(hash.wrapping_mul(FIBONACCI)),
+            _ => h2(hash),
+        }
+    }
+
+    // Enough probes to have looked at every group.
+    fn max_probes(&self) -> usize {
+        self.nbuckets() / WIDTH + 1
+    }
+
+    // set_ctrl writes a bucket's control byte, along with every copy of it
+    // after the end of the table. There's just the one copy, unless the
+    // table is smaller than a group.
+    fn set_ctrl(&mut self, at: usize, byte: u8) {
+        let nbuckets = self.nbuckets();
+        let mut at = at;
+        while at < self.ctrl.len() {
+            self.ctrl[at] = byte;
+            at += nbuckets;
+        }
+    }
+
+    // get returns the entry index in a bucket, if there is one.
+    pub(crate) fn get(&self, at: usize) -> Option<usize> {
+        match self.ctrl[at] {
+            EMPTY | DELETED => None,
+            _ => Some(self.indices[at]),
+        }
+    }
+
+    // index returns the entry index held in a bucket returned by `find`.
+    pub(crate) fn index(&self, at: usize) -> usize {
+        self.indices[at]
+    }
+
+    // find returns the bucket holding the entry with the given hash that
+    // `eq` accepts. A table with no buckets finds nothing, rather than
+    // working out a home bucket from a mask of 0 - 1.
+    pub(crate) fn find<F>(&self, hash: u64, mut eq: F) -> Option<usize>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        if self.indices.is_empty() {
+            return None;
+        }
+
+        let nbuckets = self.nbuckets();
+        let h2 = self.tag(hash);
+        let mut seq = self.probe_seq(hash);
+        for _ in 0..self.max_probes() {
+            let group = Group::load(&self.ctrl[seq.pos..]);
+            for bit in group.match_byte(h2) {
+                let at = wrap(seq.pos + bit, nbuckets);
+                if eq(self.indices[at]) {
+                    return Some(at);
+                }
+            }
+
+            // Had the entry been inserted after this group, it would have
+            // gone in the empty bucket instead, so we can stop.
+            if group.match_empty().any() {
+                return None;
+            }
+            seq.move_next(nbuckets);
+        }
+        None
+    }
+
+    // insert adds an entry index which isn't in the table yet, and returns
+    // how many groups it had to probe past before finding a free bucket.
+    // There must be at least one free bucket.
+    pub(crate) fn insert(&mut self, hash: u64, index: usize) -> usize {
+        let nbuckets = self.nbuckets();
+        let mut seq = self.probe_seq(hash);
+        for probed in 0..self.max_probes() {
+            let group = Group::load(&self.ctrl[seq.pos..]);
+            if let Some(bit) = group.match_empty_or_deleted().lowest() {
+                let at = wrap(seq.pos + bit, nbuckets);
+                if self.ctrl[at] == DELETED {
+                    self.tombstones -= 1;
+                }
+                let tag = self.tag(hash);
+                self.set_ctrl(at, tag);
+                self.indices[at] = index;
+                return probed;
+            }
+            seq.move_next(nbuckets);
+        }
+        unreachable!("inserted into a full table");
+    }
+
+    // remove empties the bucket returned by `find`, and returns the index
+    // it was holding.
+    pub(crate) fn remove(&mut self, at: usize) -> usize {
+        // If this bucket has ever been in a group with no empty buckets, a
+        // lookup could have probed straight past it, so it has to become a
+        // tombstone to keep those lookups going. Otherwise no probe ever
+        // went beyond it, and it can go straight back to being empty.
+        let nbuckets = self.nbuckets();
+        let full = |at: usize| self.ctrl[at % nbuckets] != EMPTY;
+        let after = (at..at + WIDTH).take_while(|&i| full(i)).count();
+        let before = (1..WIDTH)
+            .take_while(|&i| full(at + nbuckets * WIDTH - i))
+            .count();
+
+        if before + after >= WIDTH {
+            self.tombstones += 1;
+            self.set_ctrl(at, DELETED);
+        } else {
+            self.set_ctrl(at, EMPTY);
+        }
+        self.indices[at]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_and_remove() {
+        // Everything hashes to bucket 1 or 2 with the same top bits, so
+        // they all pile up together and have to be told apart by index.
+        let hashes = [1, 1, 2, 1, 2, 1];
+        for &nbuckets in &[8, 64] {
+            let mut table = RawTable::with_buckets(nbuckets, TableSize::PowerOfTwo);
+            for (index, &hash) in hashes.iter().enumerate() {
+                table.insert(hash, index);
+            }
+
+            for (index, &hash) in hashes.iter().enumerate() {
+                let at = table.find(hash, |i| i == index).unwrap();
+                assert_eq!(table.index(at), index);
+            }
+            assert_eq!(table.find(1 | 1 << 63, |_| true), None);
+
+            let at = table.find(1, |i| i == 0).unwrap();
+            assert_eq!(table.remove(at), 0);
+            assert_eq!(table.find(1, |i| i == 0), None);
+            for (index, &hash) in hashes.iter().enumerate().skip(1) {
+                assert!(table.find(hash, |i| i == index).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn tombstones() {
+        // A full group of colliding entries means removing any of them has
+        // to leave a tombstone, or we'd lose track of those after it.
+        let mut table = RawTable::with_buckets(4 * WIDTH, TableSize::PowerOfTwo);
+        for index in 0..2 * WIDTH {
+            table.insert(0, index);
+        }
+
+        let at = table.find(0, |i| i == 0).unwrap();
+        table.remove(at);
+        assert_eq!(table.tombstones(), 1);
+        assert!(table.find(0, |i| i == 2 * WIDTH - 1).is_some());
+
+        // Which the next insert can then re-use
+        table.insert(0, 2 * WIDTH);
+        assert_eq!(table.tombstones(), 0);
+        assert_eq!(table.find(0, |i| i == 2 * WIDTH), Some(at));
+    }
+
+    #[test]
+    fn prime_sized() {
+        // Every entry has to be found, even with them all piled up on one
+        // bucket, so the probe must reach every group.
+        for &nbuckets in &[37, 101] {
+            let mut table = RawTable::with_buckets(nbuckets, TableSize::Prime);
+            for index in 0..nbuckets - 1 {
+                table.insert(5, index);
+            }
+            for index in 0..nbuckets - 1 {
+                assert!(table.find(5, |i| i == index).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn fibonacci() {
+        // Hashes which are all multiples of 4096, like the addresses of
+        // pages, all land on the same bucket with a mask, but Fibonacci
+        // hashing spreads them about.
+        let probed = |table_size| {
+            let mut table = RawTable::with_buckets(1024, table_size);
+            let probed: usize = (0..512).map(|i| table.insert(i << 12, i as usize)).sum();
+            assert!((0..512).all(|i| table.find(i << 12, |index| index == i as usize).is_some()));
+            probed
+        };
+        assert!(probed(TableSize::PowerOfTwo) > 1000);
+        assert!(probed(TableSize::Fibonacci) < 50);
+    }
+
+    #[test]
+    fn smaller_than_a_group() {
+        for nbuckets in 1..WIDTH {
+            let mut table = RawTable::with_buckets(nbuckets, TableSize::Prime);
+            for index in 0..nbuckets {
+                table.insert(index as u64 * 7, index);
+            }
+            for index in 0..nbuckets {
+                assert!(table.find(index as u64 * 7, |i| i == index).is_some());
+            }
+        }
+    }
+}
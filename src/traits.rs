@@ -0,0 +1,215 @@
+// The standard traits for HashMap, so it can be used anywhere a
+// `std::collections::HashMap` could, and derived on structs that hold one.
+
+use std::collections;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::ops::Index;
+
+use crate::{Equivalent, HashMap, RandomState};
+
+// Cloning keeps everything, down to the order of the entries and which
+// slots they're in, and the remove eldest policy. The policy is shared, so
+// one which keeps some state of its own, like a closure counting evictions,
+// carries on counting across the original and the clone.
+impl<K, V, S> Clone for HashMap<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        HashMap {
+            buckets: self.buckets.clone(),
+            entries: self.entries.clone(),
+            free: self.free.clone(),
+            head: self.head.clone(),
+            tail: self.tail.clone(),
+            items: self.items,
+            positions: self.positions.clone(),
+            access_order: self.access_order,
+            remove_eldest: self.remove_eldest.clone(),
+            resize_policy: self.resize_policy,
+            hash_builder: self.hash_builder.clone(),
+            salt: self.salt,
+            collision_threshold: self.collision_threshold,
+            collision_stats: self.collision_stats,
+            reseeded_at: self.reseeded_at,
+            incremental_resize: self.incremental_resize,
+            old_buckets: self.old_buckets.clone(),
+            migrated: self.migrated,
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for HashMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Two maps are equal when they hold the same keys and values, whatever order
+// they're in, the same as Java's LinkedHashMap. Use `a.iter().eq(b.iter())`
+// to check the order as well.
+impl<K, V, S> PartialEq for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.peek(k) == Some(v))
+    }
+}
+
+impl<K, V, S> Eq for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Eq,
+    S: BuildHasher,
+{
+}
+
+// Extend only reserves room for half of what the iterator says it has, if
+// there's already something in the map, as a lot of them could be keys which
+// are already there, the same as std does.
+impl<K, V, S> Extend<(K, V)> for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let iter = iter.into_iter();
+        let additional = if self.is_empty() {
+            iter.size_hint().0
+        } else {
+            iter.size_hint().0.div_ceil(2)
+        };
+        self.reserve(additional);
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, S> Extend<(&'a K, &'a V)> for HashMap<K, V, S>
+where
+    K: Hash + Eq + Copy,
+    V: Copy,
+    S: BuildHasher,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = (&'a K, &'a V)>,
+    {
+        self.extend(iter.into_iter().map(|(&k, &v)| (k, v)));
+    }
+}
+
+// Indexing panics if the key isn't there, use `get` if it might not be.
+impl<K, Q, V, S> Index<&Q> for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    Q: Hash + Equivalent<K> + ?Sized,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not found in map")
+    }
+}
+
+// From an array keeps the array's order, with later duplicates replacing
+// the values of earlier ones.
+impl<K, V, const N: usize> From<[(K, V); N]> for HashMap<K, V, RandomState>
+where
+    K: Hash + Eq,
+{
+    fn from(entries: [(K, V); N]) -> Self {
+        IntoIterator::into_iter(entries).collect()
+    }
+}
+
+// A std HashMap has no order of its own, so the entries end up in whatever
+// order it iterates them in.
+impl<K, V, S, T> From<collections::HashMap<K, V, T>> for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from(map: collections::HashMap<K, V, T>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(map: &HashMap<&'static str, u32>) -> Vec<&'static str> {
+        map.keys().copied().collect()
+    }
+
+    #[test]
+    fn std_traits() {
+        #[derive(Clone, Debug, Default, PartialEq, Eq)]
+        struct Registry {
+            map: HashMap<&'static str, u32>,
+        }
+
+        let mut registry = Registry::default();
+        registry.map.extend(vec![("b", 2), ("a", 1)]);
+        registry.map.extend(&[("c", 3)].iter().copied().collect::<HashMap<_, _>>());
+        assert_eq!(format!("{:?}", registry), r#"Registry { map: {"b": 2, "a": 1, "c": 3} }"#);
+        assert_eq!(registry.map["a"], 1);
+
+        // Clones are equal, stay in the same order, and keep the policy
+        let limit = 3;
+        registry.map.set_remove_eldest(move |len, _: &_, _: &_| len > limit);
+        let mut clone = registry.clone();
+        assert_eq!(clone, registry);
+        clone.map.insert("d", 4);
+        registry.map.insert("d", 4);
+        assert_eq!(clone.map.len(), 3);
+        assert_eq!(clone, registry);
+        assert_eq!(format!("{:?}", clone.map), r#"{"a": 1, "c": 3, "d": 4}"#);
+
+        // A policy with state of its own shares it with the clone
+        let mut evictions = 0;
+        clone.map.set_remove_eldest(move |len, _: &_, _: &_| {
+            evictions += 1;
+            evictions <= 2 && len > 1
+        });
+        let mut copy = clone.clone();
+        clone.map.insert("e", 5);
+        assert_eq!(keys(&clone.map), ["d", "e"]);
+        clone.map.insert("f", 6);
+        assert_eq!(keys(&clone.map), ["d", "e", "f"]);
+        copy.map.insert("e", 5);
+        assert_eq!(keys(&copy.map), ["a", "c", "d", "e"]);
+
+        // Equality doesn't care about order
+        let a = HashMap::from([(1, "a"), (2, "b")]);
+        let b = HashMap::from([(2, "b"), (1, "a")]);
+        assert_eq!(a, b);
+        assert!(!a.iter().eq(b.iter()));
+        assert_ne!(a, HashMap::from([(1, "a"), (2, "c")]));
+        assert_ne!(a, HashMap::from([(1, "a")]));
+
+        let std: collections::HashMap<_, _> = (0..10).map(|i| (i, i * i)).collect();
+        let ours: HashMap<_, _> = HashMap::from(std.clone());
+        assert_eq!(ours.len(), 10);
+        assert!(std.iter().all(|(k, v)| ours[k] == *v));
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn index_missing() {
+        let map = HashMap::from([(1, 1)]);
+        let _ = map[&2];
+    }
+}